@@ -0,0 +1,723 @@
+use crate::audio::AUDIO_TEE_NAME;
+use crate::config::{AudioConfig, CameraConfig, RecordingConfig, SegmentConfig};
+use crate::dvr::DvrBuffer;
+use crate::gst_utils::{
+    BranchCallback, discard_tee_branch, end_tee_input, first_buffer_running_time,
+    last_buffer_end_running_time, link_tee_branch, release_tee_input, unlink_tee_branch,
+};
+use crate::profile::RecordingProfile;
+use crate::recover::unfinished_marker;
+use crate::session::Session;
+use crate::sidecar::Sidecar;
+use crate::storage::StorageManager;
+use gstreamer::prelude::*;
+use gstreamer::{Bin, ClockTime, Element, Pipeline};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// События записи, на которые подписывается интерфейс
+#[derive(Clone, Debug)]
+pub enum RecordingEvent {
+    Started {
+        path: PathBuf,
+    },
+    /// EOS отправлен, muxer дописывает файл
+    Finalizing,
+    Finished {
+        path: PathBuf,
+        duration: Duration,
+        size: u64,
+    },
+    Failed {
+        reason: String,
+    },
+    /// Предупреждение для интерфейса, запись при этом может продолжаться
+    Warning {
+        message: String,
+    },
+}
+
+/// Рассылает события всем подписчикам. Отписавшиеся удаляются при отправке.
+#[derive(Clone, Default)]
+struct EventSenders(Vec<Sender<RecordingEvent>>);
+
+impl EventSenders {
+    fn emit(&mut self, event: RecordingEvent) {
+        println!("Событие записи: {:?}", event);
+        self.0.retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+/// Откуда ветка записи получает видео
+enum BranchSource {
+    /// Ветка целиком, с кодированием, подключена к tee
+    Tee(Element),
+    /// Только muxer, подключён к выходу DVR буфера
+    Dvr,
+}
+
+/// Текущая запись
+struct ActiveRecording {
+    branch: Bin,
+    source: BranchSource,
+    /// tee со звуком, если звук пишется
+    audio_tee: Option<Element>,
+    /// Файл записи или каталог сессии при записи сегментами
+    path: PathBuf,
+    session: Option<Session>,
+    sidecar: Sidecar,
+    /// Метки оператора: смещение от начала файла и подпись
+    markers: Vec<(ClockTime, Option<String>)>,
+    /// running-time первого буфера, попавшего в файл
+    first_buffer: Arc<Mutex<Option<ClockTime>>>,
+    /// running-time конца последнего буфера, для длины записи и её
+    /// последнего сегмента
+    last_buffer: Arc<Mutex<Option<ClockTime>>>,
+    /// Счётчик выброшенных videorate кадров в начале записи
+    dropped_at_start: u64,
+}
+
+pub struct AppState {
+    pipeline: Pipeline,
+    camera: CameraConfig,
+    tee: Element,
+    /// tee со сжатыми кадрами камеры, для записи MJPEG без перекодирования
+    jpeg_tee: Element,
+    /// Источник отдаёт MJPEG
+    mjpeg_source: bool,
+    /// tee со звуком, если звук включён
+    audio_tee: Option<Element>,
+    audio: AudioConfig,
+    recording: Option<ActiveRecording>,
+    /// Предыдущая запись, которая ещё дописывается в файл
+    finalizing: Arc<Mutex<Option<ActiveRecording>>>,
+    subscribers: EventSenders,
+    profiles: BTreeMap<String, RecordingProfile>,
+    profile_name: String,
+    segments: SegmentConfig,
+    /// Длина и максимальный размер буфера предзаписи
+    preroll: Duration,
+    preroll_max_bytes: u64,
+    dvr: Option<DvrBuffer>,
+    storage: StorageManager,
+}
+
+impl AppState {
+    pub fn new(
+        pipeline: Pipeline,
+        camera: &CameraConfig,
+        recording: &RecordingConfig,
+        audio: &AudioConfig,
+        storage: StorageManager,
+    ) -> Self {
+        let tee = pipeline
+            .by_name("t")
+            .expect("Не удалось найти элемент tee в pipeline");
+        let jpeg_tee = pipeline
+            .by_name("jt")
+            .expect("Не удалось найти элемент jpeg tee в pipeline");
+        let audio_tee = pipeline.by_name(AUDIO_TEE_NAME).filter(|_| audio.enabled);
+
+        let mut state = Self {
+            pipeline,
+            camera: camera.clone(),
+            tee,
+            jpeg_tee,
+            mjpeg_source: camera.source.is_mjpeg(),
+            audio_tee,
+            audio: audio.clone(),
+            recording: None,
+            finalizing: Arc::new(Mutex::new(None)),
+            subscribers: EventSenders::default(),
+            profiles: recording.profiles.clone(),
+            profile_name: recording.profile.clone(),
+            segments: recording.segments.clone(),
+            preroll: Duration::from_secs(camera.preroll_seconds.into()),
+            preroll_max_bytes: u64::from(camera.preroll_max_mb) * 1024 * 1024,
+            dvr: None,
+            storage,
+        };
+        state.rebuild_dvr();
+        state
+    }
+
+    /// Пересоздаёт буфер предзаписи под текущий профиль. Накопленное
+    /// видео при этом теряется.
+    fn rebuild_dvr(&mut self) {
+        if let Some(dvr) = self.dvr.take() {
+            dvr.remove();
+        }
+        if self.preroll.is_zero() {
+            return;
+        }
+
+        let profile = self.profile();
+        let passthrough = profile.is_passthrough(self.mjpeg_source);
+        let tee = if passthrough {
+            &self.jpeg_tee
+        } else {
+            &self.tee
+        };
+        match DvrBuffer::new(
+            &self.pipeline,
+            tee,
+            &profile.encode_description(passthrough),
+            self.preroll,
+            self.preroll_max_bytes,
+        ) {
+            Ok(dvr) => self.dvr = Some(dvr),
+            Err(e) => println!("Не удалось создать DVR буфер, запись без предзаписи: {}", e),
+        }
+    }
+
+    /// Имена профилей записи в порядке для выбора в интерфейсе
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    pub fn profile_name(&self) -> &str {
+        &self.profile_name
+    }
+
+    /// Текущий профиль записи
+    pub fn profile(&self) -> &RecordingProfile {
+        &self.profiles[&self.profile_name]
+    }
+
+    /// Выбирает профиль для следующей записи
+    pub fn set_profile(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        if !self.profiles.contains_key(name) {
+            return Err(format!("Нет профиля записи '{}'", name).into());
+        }
+        if self.recording.is_some() {
+            return Err("Профиль нельзя сменить во время записи".into());
+        }
+        if self.profile_name == name {
+            return Ok(());
+        }
+        println!("Профиль записи: {}", name);
+        self.profile_name = name.to_string();
+        self.rebuild_dvr();
+        Ok(())
+    }
+
+    /// Новый поток событий записи
+    pub fn subscribe(&mut self) -> Receiver<RecordingEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.0.push(sender);
+        receiver
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Текущая запись и запись, которая ещё дописывается в файл
+    pub fn paths_in_use(&self) -> Vec<PathBuf> {
+        let finalizing = self.finalizing.lock().unwrap();
+        self.recording
+            .iter()
+            .chain(finalizing.iter())
+            .map(|r| r.path.clone())
+            .collect()
+    }
+
+    pub fn start_recording(&mut self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let result = self.try_start_recording(file_path);
+        if let Err(e) = &result {
+            self.subscribers.emit(RecordingEvent::Failed {
+                reason: e.to_string(),
+            });
+        }
+        result
+    }
+
+    fn try_start_recording(&mut self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        if self.recording.is_some() {
+            return Err("Запись уже идёт".into());
+        }
+        if self.finalizing.lock().unwrap().is_some() {
+            return Err("Предыдущая запись ещё сохраняется".into());
+        }
+
+        self.storage.enforce(&self.paths_in_use());
+        self.storage.check_can_start()?;
+
+        println!("Начинаем запись в файл: {}", file_path);
+
+        // При записи сегментами имя файла становится именем каталога сессии
+        let session = if self.segments.enabled() {
+            Some(Session::create(
+                &Path::new(file_path).with_extension(""),
+                self.profile().container.extension(),
+                &self.profile_name,
+                &self.segments,
+            )?)
+        } else {
+            None
+        };
+
+        let dir = session.as_ref().map(|s| s.dir().to_path_buf());
+        let result = self.start_branch(file_path, session);
+        if result.is_err()
+            && let Some(dir) = dir
+        {
+            // Сегменты ещё не писались, каталог сессии не нужен
+            let _ = fs::remove_dir_all(dir);
+        }
+        result
+    }
+
+    /// Создаёт и подключает ветку записи в файл или в каталог сессии
+    fn start_branch(
+        &mut self,
+        file_path: &str,
+        session: Option<Session>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mux = match &session {
+            Some(session) => self
+                .profile()
+                .segment_mux_description(&session.location_pattern(), &self.segments),
+            None => self.profile().mux_description(file_path),
+        };
+
+        // С DVR буфером кодирование уже идёт в нём, ветке нужен только muxer
+        let branch_str = if self.dvr.is_some() {
+            format!("queue ! {}", mux)
+        } else {
+            // MJPEG камеры пишется как есть, без jpegdec и повторного кодирования
+            let passthrough = self.profile().is_passthrough(self.mjpeg_source);
+            format!(
+                "{} ! {}",
+                self.profile().encode_description(passthrough),
+                mux
+            )
+        };
+        println!("Создаем branch с настройками: {}", branch_str);
+
+        let branch = gstreamer::parse::bin_from_description(&branch_str, true)?;
+        let audio_tee = self.audio_tee_for_profile();
+        if audio_tee.is_some() {
+            add_audio_input(
+                &branch,
+                &self.audio.codec.encode_description(self.audio.bitrate),
+            )?;
+        }
+        let first_buffer = first_buffer_running_time(&branch);
+        let last_buffer = last_buffer_end_running_time(&branch);
+        let source = if self.dvr.is_some() {
+            self.attach_to_dvr(&branch)?
+        } else {
+            self.attach_to_tee(&branch)?
+        };
+        if let Some(tee) = &audio_tee {
+            link_audio_input(tee, &branch);
+        }
+
+        // Метка снимается после завершения файла, по оставшейся метке
+        // `recover` находит оборванные записи. У сессии для этого манифест.
+        let path = match &session {
+            Some(session) => session.dir().to_path_buf(),
+            None => {
+                let path = PathBuf::from(file_path);
+                if let Err(e) = fs::write(unfinished_marker(&path), "") {
+                    println!("Не удалось создать метку записи: {}", e);
+                }
+                path
+            }
+        };
+        let sidecar = Sidecar::create(&path, &self.camera, &self.profile_name, self.profile());
+        self.recording = Some(ActiveRecording {
+            branch,
+            source,
+            audio_tee,
+            path: path.clone(),
+            session,
+            sidecar,
+            markers: Vec::new(),
+            first_buffer,
+            last_buffer,
+            dropped_at_start: self.dropped_frames(),
+        });
+        self.subscribers.emit(RecordingEvent::Started { path });
+
+        Ok(())
+    }
+
+    /// tee со звуком, если звук включён и контейнер профиля его хранит
+    fn audio_tee_for_profile(&mut self) -> Option<Element> {
+        let tee = self.audio_tee.clone()?;
+        let container = self.profile().container;
+        if !container.supports_audio(self.audio.codec) {
+            self.subscribers.emit(RecordingEvent::Warning {
+                message: format!(
+                    "{:?} не хранит звук {:?}, запись без звука",
+                    container, self.audio.codec
+                ),
+            });
+            return None;
+        }
+        Some(tee)
+    }
+
+    /// Ветка с кодированием на tee: запись начинается с момента нажатия
+    fn attach_to_tee(&self, branch: &Bin) -> Result<BranchSource, Box<dyn Error>> {
+        let passthrough = self.profile().is_passthrough(self.mjpeg_source);
+        let tee = if passthrough {
+            &self.jpeg_tee
+        } else {
+            &self.tee
+        };
+        if let Err(e) = link_tee_branch(&self.pipeline, tee, branch) {
+            discard_tee_branch(&self.pipeline, tee, branch);
+            return Err(e);
+        }
+        println!("Branch успешно подключен");
+
+        Ok(BranchSource::Tee(tee.clone()))
+    }
+
+    /// Muxer на выходе DVR буфера: в файл сначала попадает предзапись
+    fn attach_to_dvr(&mut self, branch: &Bin) -> Result<BranchSource, Box<dyn Error>> {
+        let dvr = self.dvr.as_mut().ok_or("DVR буфер не создан")?;
+        if let Err(e) = dvr.attach(branch) {
+            dvr.discard(branch);
+            return Err(e);
+        }
+        println!("Branch подключен к DVR буферу");
+
+        Ok(BranchSource::Dvr)
+    }
+
+    /// Ставит метку на текущий момент записи: в метаданные и, если muxer
+    /// это умеет (Matroska, WebM), в главы файла. Возвращает смещение
+    /// метки от начала файла.
+    pub fn add_marker(&mut self, label: Option<&str>) -> Result<Duration, Box<dyn Error>> {
+        let recording = self.recording.as_mut().ok_or("Запись не идёт")?;
+
+        let now = self.pipeline.current_running_time();
+        let start = *recording.first_buffer.lock().unwrap();
+        let offset = match (now, start) {
+            (Some(now), Some(start)) => now.saturating_sub(start),
+            _ => ClockTime::ZERO,
+        };
+        println!("Метка {} {:?}", offset, label);
+
+        recording.sidecar.add_bookmark(offset.mseconds(), label);
+        recording.markers.push((offset, label.map(str::to_string)));
+
+        // В сегментах время глав считалось бы от начала каждого файла
+        if recording.session.is_none() {
+            set_chapters(&recording.branch, &recording.markers);
+        }
+
+        Ok(Duration::from_nanos(offset.nseconds()))
+    }
+
+    /// Начинает завершение записи. Файл дописывается в фоне, об окончании
+    /// сообщает событие Finished или Failed.
+    pub fn stop_recording(&mut self) -> Result<(), Box<dyn Error>> {
+        self.update_sidecar();
+        let recording = self.recording.take().ok_or("Запись не идёт")?;
+
+        println!("Останавливаем запись...");
+        self.subscribers.emit(RecordingEvent::Finalizing);
+
+        let mut subscribers = self.subscribers.clone();
+        let branch = recording.branch.clone();
+        let source = match &recording.source {
+            BranchSource::Tee(tee) => Some(tee.clone()),
+            BranchSource::Dvr => None,
+        };
+        let audio_tee = recording.audio_tee.clone();
+        // Пока файл дописывается, запись остаётся здесь: ошибки и сообщения
+        // её ветки по-прежнему относятся к ней
+        *self.finalizing.lock().unwrap() = Some(recording);
+
+        // Ветка дописывает файл в фоне, живое видео не останавливается
+        let finalizing = self.finalizing.clone();
+        let callback: BranchCallback = Box::new(move |eos_reached| {
+            println!("Branch записи отключен");
+            let Some(mut recording) = finalizing.lock().unwrap().take() else {
+                return;
+            };
+
+            let size = match recording.session.as_mut() {
+                Some(session) => {
+                    session.finish(*recording.last_buffer.lock().unwrap(), eos_reached)
+                }
+                None => fs::metadata(&recording.path).map(|m| m.len()).unwrap_or(0),
+            };
+            recording.sidecar.finish(eos_reached);
+            let event = if eos_reached {
+                if recording.session.is_none() {
+                    let _ = fs::remove_file(unfinished_marker(&recording.path));
+                }
+                // Длина по времени буферов в файле: с предзаписью и без
+                // времени, пока ветка дописывала файл
+                let first = *recording.first_buffer.lock().unwrap();
+                let last = *recording.last_buffer.lock().unwrap();
+                let duration = match (first, last) {
+                    (Some(first), Some(last)) => last.saturating_sub(first).into(),
+                    _ => Duration::ZERO,
+                };
+                RecordingEvent::Finished {
+                    size,
+                    path: recording.path,
+                    duration,
+                }
+            } else {
+                RecordingEvent::Failed {
+                    reason: format!(
+                        "Файл {} мог остаться незавершённым",
+                        recording.path.display()
+                    ),
+                }
+            };
+            subscribers.emit(event);
+        });
+
+        // Звук завершается первым, иначе muxer ждал бы его после EOS видео
+        if let (Some(tee), Some(pad)) = (&audio_tee, branch.static_pad("audio")) {
+            end_tee_input(tee, &pad);
+        }
+
+        match (source, self.dvr.as_mut()) {
+            (Some(tee), _) => unlink_tee_branch(&self.pipeline, &tee, &branch, callback),
+            (None, Some(dvr)) => dvr.detach(&branch, callback),
+            (None, None) => callback(false),
+        }
+
+        Ok(())
+    }
+
+    /// Периодическая проверка места: удаляет старые записи сверх квоты и
+    /// останавливает запись, пока файл ещё можно корректно завершить
+    pub fn check_storage(&mut self) {
+        let deleted = self.storage.enforce(&self.paths_in_use());
+        if !deleted.is_empty() {
+            self.subscribers.emit(RecordingEvent::Warning {
+                message: format!("Удалено старых записей: {}", deleted.len()),
+            });
+        }
+
+        if self.recording.is_some() && self.storage.is_running_out() {
+            self.subscribers.emit(RecordingEvent::Warning {
+                message: String::from("Место на диске заканчивается, запись остановлена"),
+            });
+            if let Err(e) = self.stop_recording() {
+                println!("Ошибка остановки записи: {}", e);
+            }
+        }
+    }
+
+    /// Счётчик кадров, выброшенных videorate с запуска pipeline
+    fn dropped_frames(&self) -> u64 {
+        self.pipeline
+            .by_name("rate")
+            .map_or(0, |rate| rate.property::<u64>("drop"))
+    }
+
+    /// Обновляет счётчики в метаданных текущей записи
+    pub fn update_sidecar(&mut self) {
+        let dropped = self.dropped_frames();
+        if let Some(recording) = self.recording.as_mut() {
+            recording
+                .sidecar
+                .set_dropped_frames(dropped.saturating_sub(recording.dropped_at_start));
+        }
+    }
+
+    /// Ошибка pipeline попадает в метаданные идущей записи
+    pub fn log_error(&mut self, source: &str, message: &str) {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.sidecar.add_error(source, message);
+        }
+    }
+
+    /// Принадлежит ли элемент ветке текущей или дописываемой записи
+    /// или DVR буферу
+    pub fn owns(&self, object: &gstreamer::Object) -> bool {
+        self.recording
+            .as_ref()
+            .is_some_and(|r| object.has_as_ancestor(&r.branch))
+            || self.is_finalizing_branch(object)
+            || self.dvr.as_ref().is_some_and(|dvr| dvr.owns(object))
+    }
+
+    fn is_finalizing_branch(&self, object: &gstreamer::Object) -> bool {
+        self.finalizing
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|r| object.has_as_ancestor(&r.branch))
+    }
+
+    /// Сообщения splitmuxsink о начале и конце сегментов текущей или
+    /// дописываемой записи
+    pub fn handle_element_message(&mut self, message: &gstreamer::Message) {
+        let (Some(src), Some(structure)) = (message.src(), message.structure()) else {
+            return;
+        };
+        let (Ok(location), Ok(running_time)) = (
+            structure.get::<&str>("location"),
+            structure.get::<u64>("running-time"),
+        ) else {
+            return;
+        };
+
+        // Последний сегмент закрывается, когда запись уже дописывается
+        let mut finalizing = self.finalizing.lock().unwrap();
+        let Some(session) = self
+            .recording
+            .iter_mut()
+            .chain(finalizing.iter_mut())
+            .find(|r| src.has_as_ancestor(&r.branch))
+            .and_then(|r| r.session.as_mut())
+        else {
+            return;
+        };
+
+        match structure.name().as_str() {
+            "splitmuxsink-fragment-opened" => session.fragment_opened(location, running_time),
+            "splitmuxsink-fragment-closed" => session.fragment_closed(location, running_time),
+            _ => {}
+        }
+    }
+
+    /// Ошибка в ветке записи или в DVR буфере. Сломанный буфер убирается,
+    /// следующие записи идут без предзаписи. Живое видео продолжает идти.
+    pub fn handle_branch_error(&mut self, object: &gstreamer::Object, reason: &str) {
+        // Дописываемую ветку уберёт таймаут завершения, запись станет Failed
+        if self.is_finalizing_branch(object) {
+            println!("Ошибка при завершении записи: {}", reason);
+            if let Some(recording) = self.finalizing.lock().unwrap().as_mut() {
+                recording.sidecar.add_error(&object.path_string(), reason);
+            }
+            return;
+        }
+
+        let dvr_failed = self.dvr.as_ref().is_some_and(|dvr| dvr.owns(object));
+        let recording_on_dvr = self
+            .recording
+            .as_ref()
+            .is_some_and(|r| matches!(r.source, BranchSource::Dvr));
+
+        if !dvr_failed || recording_on_dvr {
+            self.abort_recording(reason);
+        }
+        if dvr_failed {
+            println!("Ошибка в DVR буфере, предзапись отключена: {}", reason);
+            if let Some(dvr) = self.dvr.take() {
+                dvr.remove();
+            }
+        }
+    }
+
+    /// Убирает ветку записи без завершения файла
+    fn abort_recording(&mut self, reason: &str) {
+        if let Some(mut recording) = self.recording.take() {
+            println!("Запись прервана: {}", reason);
+            if let (Some(tee), Some(pad)) =
+                (&recording.audio_tee, recording.branch.static_pad("audio"))
+            {
+                release_tee_input(tee, &pad);
+            }
+            match (&recording.source, self.dvr.as_mut()) {
+                (BranchSource::Tee(tee), _) => {
+                    discard_tee_branch(&self.pipeline, tee, &recording.branch)
+                }
+                (BranchSource::Dvr, Some(dvr)) => dvr.discard(&recording.branch),
+                (BranchSource::Dvr, None) => {}
+            }
+            if let Some(session) = recording.session.as_mut() {
+                session.finish(None, false);
+            }
+            recording.sidecar.finish(false);
+            self.subscribers.emit(RecordingEvent::Failed {
+                reason: reason.to_string(),
+            });
+        }
+    }
+}
+
+/// Добавляет в ветку кодирование звука и вход "audio". Звук подаётся в
+/// тот же muxer, синхронизация - по времени буферов общего pipeline.
+fn add_audio_input(branch: &Bin, encode: &str) -> Result<(), Box<dyn Error>> {
+    let mux = branch.by_name("mux").ok_or("В ветке записи нет muxer")?;
+    let audio = gstreamer::parse::bin_from_description(encode, true)?;
+    branch.add(&audio)?;
+    audio.link(&mux)?;
+
+    let sink_pad = audio
+        .static_pad("sink")
+        .ok_or("У ветки звука нет sink pad")?;
+    let ghost = gstreamer::GhostPad::builder_with_target(&sink_pad)?
+        .name("audio")
+        .build();
+    branch.add_pad(&ghost)?;
+    Ok(())
+}
+
+/// Подключает вход "audio" ветки к tee со звуком. Без звука вход сразу
+/// получает EOS, чтобы muxer его не ждал.
+fn link_audio_input(tee: &Element, branch: &Bin) {
+    let Some(sink_pad) = branch.static_pad("audio") else {
+        return;
+    };
+    let result = match tee.request_pad_simple("src_%u") {
+        Some(tee_pad) => tee_pad.link(&sink_pad).map_err(|e| {
+            tee.release_request_pad(&tee_pad);
+            e.to_string()
+        }),
+        None => Err(String::from("нет свободного pad")),
+    };
+    if let Err(e) = result {
+        println!("Не удалось подключить звук, запись без звука: {}", e);
+        end_tee_input(tee, &sink_pad);
+    }
+}
+
+/// Передаёт метки в muxer как оглавление (TOC). Muxer записывает главы при
+/// завершении файла, поэтому оглавление каждый раз задаётся целиком.
+fn set_chapters(branch: &Bin, markers: &[(ClockTime, Option<String>)]) {
+    let mut edition = gstreamer::TocEntry::new(gstreamer::TocEntryType::Edition, "markers");
+    for (i, (start, label)) in markers.iter().enumerate() {
+        let stop = markers
+            .get(i + 1)
+            .map_or(-1, |(next, _)| next.nseconds() as i64);
+        let title = label.clone().unwrap_or_else(|| format!("Метка {}", i + 1));
+
+        let mut tags = gstreamer::TagList::new();
+        tags.get_mut()
+            .unwrap()
+            .add::<gstreamer::tags::Title>(&title.as_str(), gstreamer::TagMergeMode::Replace);
+
+        let mut chapter = gstreamer::TocEntry::new(
+            gstreamer::TocEntryType::Chapter,
+            &format!("marker{}", i + 1),
+        );
+        let chapter_mut = chapter.get_mut().unwrap();
+        chapter_mut.set_start_stop_times(start.nseconds() as i64, stop);
+        chapter_mut.set_tags(tags);
+        edition.get_mut().unwrap().append_sub_entry(chapter);
+    }
+
+    let mut toc = gstreamer::Toc::new(gstreamer::TocScope::Global);
+    toc.get_mut().unwrap().append_entry(edition);
+
+    for setter in branch
+        .iterate_all_by_interface(gstreamer::TocSetter::static_type())
+        .into_iter()
+        .filter_map(Result::ok)
+    {
+        if let Some(setter) = setter.dynamic_cast_ref::<gstreamer::TocSetter>() {
+            setter.set_toc(Some(&toc));
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use gstreamer::prelude::*;
+use gstreamer::{Bin, Pipeline, State};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::error::Error;
+use std::rc::Rc;
+
+/// Имя tee со звуком в pipeline, к нему подключается ветка записи
+pub const AUDIO_TEE_NAME: &str = "at";
+
+/// Имя audioconvert в pipeline, к нему подключается bin источника звука
+const AUDIO_INPUT_NAME: &str = "ain";
+
+/// Замена источника звука после ошибки
+const SILENCE: &str = "audiotestsrc is-live=true wave=silence";
+
+/// Имя элемента level, его сообщения показывает индикатор уровня
+pub const LEVEL_NAME: &str = "level";
+
+/// Источник звука
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum AudioSource {
+    Alsa {
+        #[serde(default = "default_alsa_device")]
+        device: String,
+    },
+    Pulse {
+        #[serde(default)]
+        device: Option<String>,
+    },
+    Pipewire {
+        #[serde(default)]
+        target: Option<String>,
+    },
+    /// Тон audiotestsrc, для проверки без микрофона
+    #[serde(rename = "test")]
+    TestSrc {
+        #[serde(default = "default_freq")]
+        freq: f64,
+    },
+}
+
+fn default_alsa_device() -> String {
+    String::from("default")
+}
+
+fn default_freq() -> f64 {
+    440.0
+}
+
+/// Кодек звука в записи
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCodec {
+    Aac,
+    Opus,
+}
+
+impl AudioSource {
+    /// Значения по умолчанию для типа источника из командной строки
+    pub fn default_for(kind: &str) -> Option<Self> {
+        match kind {
+            "alsa" => Some(AudioSource::Alsa {
+                device: default_alsa_device(),
+            }),
+            "pulse" => Some(AudioSource::Pulse { device: None }),
+            "pipewire" => Some(AudioSource::Pipewire { target: None }),
+            "test" => Some(AudioSource::TestSrc {
+                freq: default_freq(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Задаёт устройство для источников, у которых оно есть
+    pub fn set_device(&mut self, value: &str) {
+        match self {
+            AudioSource::Alsa { device } => *device = value.to_string(),
+            AudioSource::Pulse { device } => *device = Some(value.to_string()),
+            AudioSource::Pipewire { target } => *target = Some(value.to_string()),
+            AudioSource::TestSrc { .. } => {}
+        }
+    }
+
+    fn element(&self) -> String {
+        match self {
+            AudioSource::Alsa { device } => format!("alsasrc device=\"{}\"", device),
+            AudioSource::Pulse {
+                device: Some(device),
+            } => {
+                format!("pulsesrc device=\"{}\"", device)
+            }
+            AudioSource::Pulse { device: None } => String::from("pulsesrc"),
+            AudioSource::Pipewire {
+                target: Some(target),
+            } => format!("pipewiresrc target-object=\"{}\"", target),
+            AudioSource::Pipewire { target: None } => String::from("pipewiresrc"),
+            AudioSource::TestSrc { freq } => {
+                format!("audiotestsrc is-live=true wave=sine freq={}", freq)
+            }
+        }
+    }
+}
+
+/// Часть pipeline от входа для источника до `tee name=at`. Сам источник
+/// подключает `AudioInput`. level по пути каждые 100 мс сообщает уровень
+/// для индикатора.
+pub fn pipeline_segment() -> String {
+    format!(
+        "audioconvert name={} ! audioresample ! audio/x-raw,rate=48000,channels=2 ! \
+        level name={} post-messages=true interval=100000000 ! \
+        tee name={} allow-not-linked=true",
+        AUDIO_INPUT_NAME, LEVEL_NAME, AUDIO_TEE_NAME
+    )
+}
+
+/// Держит источник звука отдельным bin на входе звуковой части pipeline.
+/// Ошибка микрофона не трогает видео: источник заменяется тишиной, и
+/// запись продолжается без звука.
+#[derive(Clone)]
+pub struct AudioInput {
+    pipeline: Pipeline,
+    bin: Rc<RefCell<Option<Bin>>>,
+}
+
+impl AudioInput {
+    /// Подключает источник из настроек, а если он не открылся - тишину
+    pub fn new(pipeline: &Pipeline, source: &AudioSource) -> Self {
+        let input = Self {
+            pipeline: pipeline.clone(),
+            bin: Rc::new(RefCell::new(None)),
+        };
+        if let Err(e) = input.attach(&source.element()) {
+            println!("Не удалось подключить источник звука: {}", e);
+            input.fall_back_to_silence();
+        }
+        input
+    }
+
+    /// Пришло ли сообщение от источника звука
+    pub fn owns(&self, object: &gstreamer::Object) -> bool {
+        match &*self.bin.borrow() {
+            Some(bin) => object.has_as_ancestor(bin),
+            None => false,
+        }
+    }
+
+    /// Ошибка источника звука: отключаем его и подаём тишину
+    pub fn handle_source_error(&self) {
+        println!("Источник звука отключён, дальше записывается тишина");
+        self.fall_back_to_silence();
+    }
+
+    fn fall_back_to_silence(&self) {
+        self.detach();
+        if let Err(e) = self.attach(SILENCE) {
+            println!("Не удалось подключить тишину вместо звука: {}", e);
+            self.detach();
+        }
+    }
+
+    fn attach(&self, description: &str) -> Result<(), Box<dyn Error>> {
+        let bin = gstreamer::parse::bin_from_description(description, true)?;
+        // Устройство открывается при переходе в Ready. Пока bin не в
+        // pipeline, ошибка открытия не попадает на шину.
+        if bin.set_state(State::Ready).is_err() {
+            let _ = bin.set_state(State::Null);
+            return Err("источник не открылся".into());
+        }
+        self.pipeline.add(&bin)?;
+        *self.bin.borrow_mut() = Some(bin.clone());
+
+        let input = self
+            .pipeline
+            .by_name(AUDIO_INPUT_NAME)
+            .ok_or("В pipeline нет входа для звука")?;
+        bin.link(&input)?;
+        bin.sync_state_with_parent()?;
+        Ok(())
+    }
+
+    fn detach(&self) {
+        if let Some(bin) = self.bin.borrow_mut().take() {
+            let _ = bin.set_state(State::Null);
+            if let Some(src_pad) = bin.static_pad("src")
+                && let Some(peer) = src_pad.peer()
+            {
+                let _ = src_pad.unlink(&peer);
+            }
+            let _ = self.pipeline.remove(&bin);
+        }
+    }
+}
+
+impl AudioCodec {
+    /// Ветка кодирования звука от tee до muxer, `bitrate` в кбит/с
+    pub fn encode_description(&self, bitrate: u32) -> String {
+        match self {
+            AudioCodec::Aac => format!(
+                "queue ! audioconvert ! avenc_aac bitrate={} ! aacparse",
+                bitrate * 1000
+            ),
+            AudioCodec::Opus => format!(
+                "queue ! audioconvert ! opusenc bitrate={} ! opusparse",
+                bitrate * 1000
+            ),
+        }
+    }
+}
+
+/// Пиковый уровень из сообщения level, от 0.0 (тишина, -60 дБ и ниже)
+/// до 1.0 (0 дБ). Берётся самый громкий канал.
+pub fn peak_level(structure: &gstreamer::StructureRef) -> Option<f64> {
+    if structure.name().as_str() != "level" {
+        return None;
+    }
+    let peaks = structure.get::<gstreamer::glib::ValueArray>("peak").ok()?;
+    let peak_db = peaks
+        .as_slice()
+        .iter()
+        .filter_map(|value| value.get::<f64>().ok())
+        .fold(f64::NEG_INFINITY, f64::max);
+
+    Some(((peak_db + 60.0) / 60.0).clamp(0.0, 1.0))
+}
@@ -0,0 +1,161 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Аргументы командной строки. Любое заданное поле перекрывает значение
+/// из файла конфигурации.
+#[derive(Parser, Debug, Default)]
+#[command(name = "ncy_gtk", about = "Видео с камеры в GTK окне")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Путь к файлу конфигурации (вместо системного и пользовательского)
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Вывести итоговую конфигурацию и выйти
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Вывести найденные видео устройства и их режимы и выйти
+    #[arg(long)]
+    pub list_devices: bool,
+
+    /// Ширина кадра
+    #[arg(long)]
+    pub width: Option<i32>,
+
+    /// Высота кадра
+    #[arg(long)]
+    pub height: Option<i32>,
+
+    /// Частота кадров
+    #[arg(long)]
+    pub fps: Option<i32>,
+
+    /// Каталог для записей
+    #[arg(long, value_name = "DIR")]
+    pub path: Option<String>,
+
+    /// Через сколько миллисекунд без кадров показывать заставку
+    #[arg(long, value_name = "MS")]
+    pub no_signal_timeout: Option<u64>,
+
+    /// Сколько секунд до нажатия "Запись" сохранять в файл, 0 - выключено
+    #[arg(long, value_name = "SECONDS")]
+    pub preroll: Option<u32>,
+
+    /// Ограничение памяти буфера предзаписи, МБ
+    #[arg(long, value_name = "MB")]
+    pub preroll_max_mb: Option<u32>,
+
+    /// Сколько последних секунд показывать кнопкой "Повтор", 0 - выключено
+    #[arg(long, value_name = "SECONDS")]
+    pub replay: Option<u32>,
+
+    /// Профиль записи из [recording.profiles]
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Делить запись на файлы не длиннее заданного числа секунд
+    #[arg(long, value_name = "SECONDS")]
+    pub segment_seconds: Option<u64>,
+
+    /// Делить запись на файлы не больше заданного размера, МБ
+    #[arg(long, value_name = "MB")]
+    pub segment_mb: Option<u64>,
+
+    /// Сколько МБ могут занимать записи, старые удаляются
+    #[arg(long, value_name = "MB")]
+    pub quota_mb: Option<u64>,
+
+    /// Не начинать запись, если свободно меньше, МБ
+    #[arg(long, value_name = "MB")]
+    pub min_free_mb: Option<u64>,
+
+    /// Записывать звук из источника: alsa, pulse, pipewire, test
+    #[arg(long, value_name = "TYPE")]
+    pub audio: Option<String>,
+
+    /// Устройство источника звука
+    #[arg(long, value_name = "DEVICE")]
+    pub audio_device: Option<String>,
+
+    /// Не записывать звук, даже если он включён в конфигурации
+    #[arg(long)]
+    pub no_audio: bool,
+
+    /// Позывной для имён записей
+    #[arg(long)]
+    pub callsign: Option<String>,
+
+    /// Тип источника: v4l2, test, file, rtp, rtsp
+    #[arg(long, value_name = "TYPE")]
+    pub source: Option<String>,
+
+    /// V4L2 устройство
+    #[arg(long)]
+    pub device: Option<String>,
+
+    /// Формат V4L2 устройства: mjpeg, yuy2, nv12, h264, raw
+    #[arg(long)]
+    pub format: Option<String>,
+
+    /// Шаблон videotestsrc
+    #[arg(long)]
+    pub pattern: Option<String>,
+
+    /// Файл для воспроизведения
+    #[arg(long, value_name = "FILE")]
+    pub file: Option<String>,
+
+    /// UDP порт RTP потока
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Кодек RTP потока: h264, h265
+    #[arg(long)]
+    pub codec: Option<String>,
+
+    /// Тип нагрузки RTP
+    #[arg(long)]
+    pub payload: Option<u8>,
+
+    /// Задержка jitter буфера, мс
+    #[arg(long)]
+    pub latency: Option<u32>,
+
+    /// Адрес RTSP потока
+    #[arg(long, value_name = "URL")]
+    pub location: Option<String>,
+
+    /// Транспорт RTSP: auto, tcp, udp
+    #[arg(long)]
+    pub transport: Option<String>,
+
+    /// Имя пользователя RTSP
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Пароль RTSP
+    #[arg(long)]
+    pub password: Option<String>,
+
+    /// Через сколько секунд без кадров переподключаться к RTSP
+    #[arg(long, value_name = "SEC")]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Найти незавершённые записи (после сбоя или отключения питания)
+    /// и пересобрать их в воспроизводимые файлы
+    Recover {
+        /// Каталог с записями, по умолчанию - из конфигурации
+        dir: Option<PathBuf>,
+
+        /// Только показать найденные файлы
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
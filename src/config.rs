@@ -0,0 +1,628 @@
+use crate::audio::{AudioCodec, AudioSource};
+use crate::cli::Cli;
+use crate::naming::{self, Clock, TargetFs};
+use crate::profile::{RecordingProfile, default_profiles};
+use crate::snapshot::SnapshotFormat;
+use crate::video_source::{PixelFormat, RtpCodec, RtspTransport, VideoSource};
+use gstreamer::Fraction;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SYSTEM_CONFIG: &str = "/etc/ncy_gtk/config.toml";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub camera: CameraConfig,
+    pub recording: RecordingConfig,
+    pub storage: StorageConfig,
+    pub audio: AudioConfig,
+    pub naming: NamingConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub width: i32,
+    pub height: i32,
+    pub fps: i32,
+    /// Точная частота из режима камеры, например 30000/1001. Подбирается
+    /// при запуске, см. `discovery::fit_to_modes`.
+    #[serde(skip)]
+    pub exact_framerate: Option<Fraction>,
+    pub path: String,
+    /// Через сколько миллисекунд без кадров показывать заставку "NO SIGNAL"
+    pub no_signal_timeout_ms: u64,
+    /// Сколько секунд видео до нажатия "Запись" попадает в файл, 0 - выключено
+    pub preroll_seconds: u32,
+    /// Ограничение памяти буфера предзаписи в МБ, 0 - только по времени
+    pub preroll_max_mb: u32,
+    /// Формат снимков кадра: jpeg или png
+    pub snapshot_format: SnapshotFormat,
+    /// Сколько последних секунд видео хранить для повтора, 0 - выключено
+    pub replay_seconds: u32,
+    pub source: VideoSource,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    /// Профиль, выбранный при запуске
+    pub profile: String,
+    pub profiles: BTreeMap<String, RecordingProfile>,
+    pub segments: SegmentConfig,
+}
+
+/// Разбиение записи на файлы. Оба ограничения 0 - запись одним файлом.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SegmentConfig {
+    /// Длина сегмента, секунды
+    pub max_seconds: u64,
+    /// Размер сегмента, МБ
+    pub max_mb: u64,
+}
+
+impl SegmentConfig {
+    pub fn enabled(&self) -> bool {
+        self.max_seconds > 0 || self.max_mb > 0
+    }
+}
+
+/// Звук в записях
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub enabled: bool,
+    pub source: AudioSource,
+    pub codec: AudioCodec,
+    /// Битрейт, кбит/с
+    pub bitrate: u32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: AudioSource::Pulse { device: None },
+            codec: AudioCodec::Aac,
+            bitrate: 128,
+        }
+    }
+}
+
+/// Имена файлов записей
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NamingConfig {
+    /// Шаблон имени без расширения, см. config.example.toml
+    pub template: String,
+    /// Время в имени: по местным часам или UTC
+    pub clock: Clock,
+    /// Позывной для подстановки `{callsign}`
+    pub callsign: String,
+    /// Складывать записи в каталоги по дням
+    pub subdir_per_day: bool,
+    /// Под какую файловую систему подгонять имена
+    pub filesystem: TargetFs,
+}
+
+impl Default for NamingConfig {
+    fn default() -> Self {
+        Self {
+            template: String::from("{date}_{time}_{callsign}_{profile}"),
+            clock: Clock::Local,
+            callsign: String::new(),
+            subdir_per_day: false,
+            filesystem: TargetFs::Fat,
+        }
+    }
+}
+
+/// Место в каталоге записей
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Сколько МБ могут занимать записи, старые удаляются. 0 - без квоты
+    pub quota_mb: u64,
+    /// Меньше этого свободного места запись не начинается
+    pub min_free_mb: u64,
+    /// Меньше этого свободного места идущая запись останавливается
+    pub stop_free_mb: u64,
+    /// Удалять старые записи, когда свободного места меньше min_free_mb
+    pub delete_oldest: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            quota_mb: 0,
+            min_free_mb: 1024,
+            stop_free_mb: 256,
+            delete_oldest: false,
+        }
+    }
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            profile: String::from("default"),
+            profiles: default_profiles(),
+            segments: SegmentConfig::default(),
+        }
+    }
+}
+
+impl CameraConfig {
+    /// Частота кадров для caps: точная из режима камеры или `fps/1`
+    pub fn framerate(&self) -> Fraction {
+        self.exact_framerate.unwrap_or(Fraction::new(self.fps, 1))
+    }
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        Self {
+            width: 720,
+            height: 480,
+            fps: 25,
+            exact_framerate: None,
+            path: String::from("src/media/"),
+            no_signal_timeout_ms: 1000,
+            preroll_seconds: 0,
+            preroll_max_mb: 0,
+            snapshot_format: SnapshotFormat::Jpeg,
+            replay_seconds: 0,
+            source: VideoSource::V4l2 {
+                device: String::from("/dev/video0"),
+                format: PixelFormat::Mjpeg,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => {
+                write!(f, "не удалось прочитать {}: {}", path.display(), e)
+            }
+            ConfigError::Parse(path, e) => write!(f, "ошибка в {}: {}", path.display(), e),
+            ConfigError::Invalid(msg) => write!(f, "неверная конфигурация: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Собирает итоговую конфигурацию: значения по умолчанию, затем системный
+    /// и пользовательский файлы (или файл из `--config`), затем аргументы
+    /// командной строки.
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        // Файлы накладываются на значения по умолчанию, поэтому в таблице
+        // источника можно задать только те поля, которые отличаются
+        let mut table = toml::Table::try_from(Config::default())
+            .expect("Конфигурация всегда сериализуется в TOML");
+
+        let files = match &cli.config {
+            Some(path) => {
+                if !path.exists() {
+                    return Err(ConfigError::Invalid(format!(
+                        "файл {} не найден",
+                        path.display()
+                    )));
+                }
+                vec![path.clone()]
+            }
+            None => config_locations(),
+        };
+
+        for path in files.iter().filter(|p| p.exists()) {
+            eprintln!("Читаем конфигурацию из {}", path.display());
+            merge_tables(&mut table, read_table(path)?);
+        }
+
+        let mut config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| ConfigError::Parse(PathBuf::from("<config>"), e))?;
+
+        config.apply_overrides(cli)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn apply_overrides(&mut self, cli: &Cli) -> Result<(), ConfigError> {
+        if let Some(profile) = &cli.profile {
+            self.recording.profile = profile.clone();
+        }
+        override_field(
+            &mut self.recording.segments.max_seconds,
+            &cli.segment_seconds,
+        );
+        override_field(&mut self.recording.segments.max_mb, &cli.segment_mb);
+        override_field(&mut self.storage.quota_mb, &cli.quota_mb);
+        override_field(&mut self.storage.min_free_mb, &cli.min_free_mb);
+
+        if let Some(kind) = &cli.audio {
+            self.audio.enabled = true;
+            self.audio.source = AudioSource::default_for(kind).ok_or_else(|| {
+                ConfigError::Invalid(format!("неизвестный источник звука '{}'", kind))
+            })?;
+        }
+        if let Some(device) = &cli.audio_device {
+            self.audio.source.set_device(device);
+        }
+        if cli.no_audio {
+            self.audio.enabled = false;
+        }
+        override_field(&mut self.naming.callsign, &cli.callsign);
+
+        let camera = &mut self.camera;
+
+        if let Some(width) = cli.width {
+            camera.width = width;
+        }
+        if let Some(height) = cli.height {
+            camera.height = height;
+        }
+        if let Some(fps) = cli.fps {
+            camera.fps = fps;
+        }
+        if let Some(path) = &cli.path {
+            camera.path = path.clone();
+        }
+        if let Some(timeout) = cli.no_signal_timeout {
+            camera.no_signal_timeout_ms = timeout;
+        }
+        override_field(&mut camera.preroll_seconds, &cli.preroll);
+        override_field(&mut camera.preroll_max_mb, &cli.preroll_max_mb);
+        override_field(&mut camera.replay_seconds, &cli.replay);
+
+        // Смена типа источника начинается со значений по умолчанию для этого
+        // типа, остальные аргументы уточняют поля выбранного варианта
+        if let Some(kind) = &cli.source {
+            camera.source = VideoSource::default_for(kind).ok_or_else(|| {
+                ConfigError::Invalid(format!("неизвестный тип источника '{}'", kind))
+            })?;
+        }
+
+        let format = match cli.format.as_deref() {
+            Some(f) => Some(
+                PixelFormat::parse(f)
+                    .ok_or_else(|| ConfigError::Invalid(format!("неизвестный формат '{}'", f)))?,
+            ),
+            None => None,
+        };
+
+        let codec = match cli.codec.as_deref() {
+            Some(c) => Some(
+                RtpCodec::parse(c)
+                    .ok_or_else(|| ConfigError::Invalid(format!("неизвестный кодек '{}'", c)))?,
+            ),
+            None => None,
+        };
+
+        let transport =
+            match cli.transport.as_deref() {
+                Some(t) => Some(RtspTransport::parse(t).ok_or_else(|| {
+                    ConfigError::Invalid(format!("неизвестный транспорт '{}'", t))
+                })?),
+                None => None,
+            };
+
+        match &mut camera.source {
+            VideoSource::V4l2 {
+                device,
+                format: pixel_format,
+            } => {
+                override_field(device, &cli.device);
+                override_field(pixel_format, &format);
+            }
+            VideoSource::TestSrc { pattern } => override_field(pattern, &cli.pattern),
+            VideoSource::File { path } => override_field(path, &cli.file),
+            VideoSource::Rtp {
+                port,
+                codec: rtp_codec,
+                payload,
+                latency,
+            } => {
+                override_field(port, &cli.port);
+                override_field(rtp_codec, &codec);
+                override_field(payload, &cli.payload);
+                override_field(latency, &cli.latency);
+            }
+            VideoSource::Rtsp {
+                location,
+                latency,
+                transport: rtsp_transport,
+                user,
+                password,
+                timeout,
+            } => {
+                override_field(location, &cli.location);
+                override_field(latency, &cli.latency);
+                override_field(rtsp_transport, &transport);
+                if cli.user.is_some() {
+                    *user = cli.user.clone();
+                }
+                if cli.password.is_some() {
+                    *password = cli.password.clone();
+                }
+                override_field(timeout, &cli.timeout);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let camera = &self.camera;
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+
+        for (name, profile) in &self.recording.profiles {
+            if let Err(msg) = profile.validate() {
+                return invalid(format!("профиль записи '{}': {}", name, msg));
+            }
+        }
+        if !self
+            .recording
+            .profiles
+            .contains_key(&self.recording.profile)
+        {
+            return invalid(format!(
+                "профиль записи '{}' не описан в [recording.profiles]",
+                self.recording.profile
+            ));
+        }
+
+        if let Err(msg) = naming::validate_template(&self.naming.template) {
+            return invalid(format!("шаблон имени записи: {}", msg));
+        }
+
+        if self.storage.stop_free_mb > self.storage.min_free_mb {
+            return invalid(format!(
+                "stop_free_mb {} больше min_free_mb {}: запись остановится сразу после начала",
+                self.storage.stop_free_mb, self.storage.min_free_mb
+            ));
+        }
+
+        if self.audio.enabled && !(8..=512).contains(&self.audio.bitrate) {
+            return invalid(format!(
+                "битрейт звука {} кбит/с вне диапазона 8..512",
+                self.audio.bitrate
+            ));
+        }
+
+        if camera.width <= 0 || camera.height <= 0 {
+            return invalid(format!(
+                "размер кадра {}x{} должен быть положительным",
+                camera.width, camera.height
+            ));
+        }
+        if !(1..=240).contains(&camera.fps) {
+            return invalid(format!("fps {} вне диапазона 1..240", camera.fps));
+        }
+        if camera.path.trim().is_empty() {
+            return invalid(String::from("не задан каталог для записей"));
+        }
+        if camera.no_signal_timeout_ms < 100 {
+            return invalid(format!(
+                "no_signal_timeout_ms {} слишком мал, минимум 100",
+                camera.no_signal_timeout_ms
+            ));
+        }
+        if camera.preroll_seconds > 300 {
+            return invalid(format!(
+                "preroll_seconds {} слишком велик, максимум 300",
+                camera.preroll_seconds
+            ));
+        }
+        if camera.replay_seconds > 60 {
+            return invalid(format!(
+                "replay_seconds {} слишком велик, максимум 60",
+                camera.replay_seconds
+            ));
+        }
+
+        match &camera.source {
+            VideoSource::V4l2 { device, .. } if device.is_empty() => {
+                invalid(String::from("не задано V4L2 устройство"))
+            }
+            VideoSource::File { path } if !Path::new(path).is_file() => {
+                invalid(format!("файл источника {} не найден", path))
+            }
+            VideoSource::Rtp { port: 0, .. } => {
+                invalid(String::from("не задан UDP порт RTP потока"))
+            }
+            VideoSource::Rtp { payload, .. } if !(96..=127).contains(payload) => invalid(format!(
+                "тип нагрузки RTP {} вне диапазона 96..127",
+                payload
+            )),
+            VideoSource::Rtsp { location, .. } if !location.starts_with("rtsp") => {
+                invalid(format!("адрес '{}' не похож на RTSP", location))
+            }
+            VideoSource::Rtsp { timeout: 0, .. } => invalid(String::from(
+                "timeout RTSP источника должен быть больше нуля",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Итоговая конфигурация в формате TOML, для `--print-config`
+    pub fn to_toml(&self) -> String {
+        let mut config = self.clone();
+        config.camera.source = config.camera.source.without_secrets();
+        toml::to_string_pretty(&config).expect("Конфигурация всегда сериализуется в TOML")
+    }
+}
+
+fn override_field<T: Clone>(field: &mut T, value: &Option<T>) {
+    if let Some(value) = value {
+        *field = value.clone();
+    }
+}
+
+/// Системный файл, затем пользовательский: последний имеет приоритет
+fn config_locations() -> Vec<PathBuf> {
+    let mut locations = vec![PathBuf::from(SYSTEM_CONFIG)];
+
+    let user_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+
+    if let Some(dir) = user_dir {
+        locations.push(dir.join("ncy_gtk").join("config.toml"));
+    }
+
+    locations
+}
+
+fn read_table(path: &Path) -> Result<toml::Table, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+    text.parse::<toml::Table>()
+        .map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+}
+
+/// Рекурсивно накладывает `overlay` на `base`. Вложенные таблицы сливаются,
+/// остальные значения заменяются. Смена типа источника заменяет таблицу
+/// целиком, иначе поля разных вариантов перемешаются.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_inner)), toml::Value::Table(inner))
+                if base_inner.get("type") == inner.get("type") || !inner.contains_key("type") =>
+            {
+                merge_tables(base_inner, inner)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Файл конфигурации во временном каталоге, удаляется после теста
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str, text: &str) -> Self {
+            let path =
+                env::temp_dir().join(format!("ncy_config_{}_{}.toml", name, std::process::id()));
+            fs::write(&path, text).unwrap();
+            Self(path)
+        }
+
+        fn cli(&self) -> Cli {
+            Cli {
+                config: Some(self.0.clone()),
+                ..Cli::default()
+            }
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn invalid_message(result: Result<Config, ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid(msg)) => msg,
+            other => panic!("ожидалась ошибка проверки, получено {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cli_overrides_file() {
+        let file = TestFile::new("override", "[camera]\nwidth = 640\nfps = 30\n");
+        let cli = Cli {
+            width: Some(1280),
+            ..file.cli()
+        };
+
+        let config = Config::load(&cli).unwrap();
+        assert_eq!(config.camera.width, 1280);
+        assert_eq!(config.camera.fps, 30);
+        assert_eq!(config.camera.height, CameraConfig::default().height);
+    }
+
+    #[test]
+    fn file_table_merges_into_defaults() {
+        let file = TestFile::new("merge", "[camera.source]\ndevice = \"/dev/video2\"\n");
+
+        let config = Config::load(&file.cli()).unwrap();
+        assert!(matches!(
+            config.camera.source,
+            VideoSource::V4l2 { ref device, format: PixelFormat::Mjpeg } if device == "/dev/video2"
+        ));
+        assert_eq!(config.camera.width, CameraConfig::default().width);
+        assert_eq!(config.recording.profile, RecordingConfig::default().profile);
+    }
+
+    #[test]
+    fn merge_tables_replaces_source_of_other_type() {
+        let mut base: toml::Table =
+            "[source]\ntype = \"v4l2\"\ndevice = \"/dev/video0\"\n[other]\nkeep = 1\n"
+                .parse()
+                .unwrap();
+        let overlay: toml::Table = "[source]\ntype = \"test\"\npattern = \"ball\"\n"
+            .parse()
+            .unwrap();
+
+        merge_tables(&mut base, overlay);
+        let source = base["source"].as_table().unwrap();
+        assert_eq!(source["type"].as_str(), Some("test"));
+        assert!(!source.contains_key("device"));
+        assert_eq!(base["other"]["keep"].as_integer(), Some(1));
+    }
+
+    #[test]
+    fn empty_path_is_rejected() {
+        let file = TestFile::new("empty_path", "");
+        let cli = Cli {
+            path: Some(String::from("  ")),
+            ..file.cli()
+        };
+
+        assert!(invalid_message(Config::load(&cli)).contains("каталог"));
+    }
+
+    #[test]
+    fn missing_source_file_is_rejected() {
+        let file = TestFile::new(
+            "missing_source",
+            "[camera.source]\ntype = \"file\"\npath = \"/nonexistent/ncy_test.mp4\"\n",
+        );
+
+        assert!(invalid_message(Config::load(&file.cli())).contains("ncy_test.mp4"));
+    }
+
+    #[test]
+    fn missing_config_file_is_rejected() {
+        let cli = Cli {
+            config: Some(env::temp_dir().join("ncy_config_missing.toml")),
+            ..Cli::default()
+        };
+
+        assert!(invalid_message(Config::load(&cli)).contains("не найден"));
+    }
+}
@@ -0,0 +1,519 @@
+use crate::config::CameraConfig;
+use crate::video_source::{PixelFormat, VideoSource};
+use gstreamer::glib;
+use gstreamer::prelude::*;
+use gstreamer::{Caps, Device, DeviceMonitor, Fraction, FractionRange, List};
+use std::error::Error;
+
+/// Видео устройство, найденное через DeviceMonitor
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub name: String,
+    /// Путь к устройству, например /dev/video0
+    pub path: Option<String>,
+    pub modes: Vec<CapsMode>,
+}
+
+/// Один вариант caps устройства: формат, разрешение и доступные частоты кадров
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapsMode {
+    /// "MJPEG" для image/jpeg, "H264" для video/x-h264,
+    /// иначе формат video/x-raw (YUY2, NV12, ...)
+    pub format: String,
+    pub width: i32,
+    pub height: i32,
+    pub framerates: Framerates,
+}
+
+/// Частоты кадров режима
+#[derive(Clone, Debug, PartialEq)]
+pub enum Framerates {
+    /// Отдельные значения, пустой список - частота неизвестна
+    List(Vec<Fraction>),
+    /// Любая частота от `min` до `max`
+    Range { min: Fraction, max: Fraction },
+}
+
+/// Режим, который будет запрошен у камеры
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModeChoice {
+    pub format: PixelFormat,
+    pub width: i32,
+    pub height: i32,
+    /// Точная частота из caps, например 30000/1001
+    pub framerate: Fraction,
+}
+
+impl CapsMode {
+    pub fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::from_caps_format(&self.format)
+    }
+}
+
+/// Перечисляет видео источники системы
+pub fn list_devices() -> Result<Vec<DeviceInfo>, Box<dyn Error>> {
+    let monitor = DeviceMonitor::new();
+    monitor.add_filter(Some("Video/Source"), None);
+    monitor.start()?;
+    let devices = monitor
+        .devices()
+        .into_iter()
+        .map(|d| device_info(&d))
+        .collect();
+    monitor.stop();
+
+    Ok(devices)
+}
+
+/// Описание устройства из его свойств и caps
+pub fn device_info(device: &Device) -> DeviceInfo {
+    let path = device.properties().and_then(|props| {
+        ["device.path", "api.v4l2.path", "object.path"]
+            .iter()
+            .find_map(|key| props.get::<String>(*key).ok())
+    });
+
+    DeviceInfo {
+        name: device.display_name().to_string(),
+        path,
+        modes: device
+            .caps()
+            .map(|caps| parse_caps(&caps))
+            .unwrap_or_default(),
+    }
+}
+
+/// Разбирает caps на отдельные режимы. Структуры с диапазонами размеров
+/// пропускаются: камеры V4L2 отдают фиксированные разрешения.
+pub fn parse_caps(caps: &Caps) -> Vec<CapsMode> {
+    let mut modes = Vec::new();
+
+    for s in caps.iter() {
+        let formats = match s.name().as_str() {
+            "image/jpeg" => vec![String::from("MJPEG")],
+            "video/x-h264" => vec![String::from("H264")],
+            "video/x-raw" => s.value("format").map(string_values).unwrap_or_default(),
+            _ => continue,
+        };
+
+        let (Ok(width), Ok(height)) = (s.get::<i32>("width"), s.get::<i32>("height")) else {
+            continue;
+        };
+
+        let framerates = s
+            .value("framerate")
+            .map(framerate_values)
+            .unwrap_or(Framerates::List(Vec::new()));
+
+        for format in formats {
+            modes.push(CapsMode {
+                format,
+                width,
+                height,
+                framerates: framerates.clone(),
+            });
+        }
+    }
+
+    modes
+}
+
+fn string_values(value: &glib::SendValue) -> Vec<String> {
+    if let Ok(s) = value.get::<String>() {
+        return vec![s];
+    }
+    value
+        .get::<List>()
+        .map(|list| {
+            list.as_slice()
+                .iter()
+                .filter_map(|v| v.get::<String>().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn framerate_values(value: &glib::SendValue) -> Framerates {
+    if let Ok(f) = value.get::<Fraction>() {
+        return Framerates::List(vec![f]);
+    }
+    if let Ok(range) = value.get::<FractionRange>() {
+        return Framerates::Range {
+            min: range.min(),
+            max: range.max(),
+        };
+    }
+    Framerates::List(
+        value
+            .get::<List>()
+            .map(|list| {
+                list.as_slice()
+                    .iter()
+                    .filter_map(|v| v.get::<Fraction>().ok())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    )
+}
+
+fn fraction_value(f: &Fraction) -> f64 {
+    if f.denom() == 0 {
+        return 0.0;
+    }
+    f.numer() as f64 / f.denom() as f64
+}
+
+/// Округлённая частота, только для сравнения режимов и поля `fps`
+fn fraction_to_fps(f: &Fraction) -> i32 {
+    fraction_value(f).round() as i32
+}
+
+impl Framerates {
+    /// Частоты, из которых выбирается ближайшая к `fps`. Из диапазона
+    /// берётся сама `fps`, прижатая к его границам.
+    fn candidates(&self, fps: i32) -> Vec<Fraction> {
+        let wanted = Fraction::new(fps, 1);
+        match self {
+            Framerates::List(rates) if rates.is_empty() => vec![wanted],
+            Framerates::List(rates) => rates.clone(),
+            Framerates::Range { min, max } => {
+                let value = fps as f64;
+                if value < fraction_value(min) {
+                    vec![*min]
+                } else if value > fraction_value(max) {
+                    vec![*max]
+                } else {
+                    vec![wanted]
+                }
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        let fraction = |f: &Fraction| format!("{}/{}", f.numer(), f.denom());
+        match self {
+            Framerates::List(rates) => rates.iter().map(fraction).collect::<Vec<_>>().join(", "),
+            Framerates::Range { min, max } => format!("{} - {}", fraction(min), fraction(max)),
+        }
+    }
+}
+
+/// Выбирает режим, ближайший к настройкам камеры: сначала предпочтительный
+/// формат, затем разрешение по площади кадра, затем частота кадров.
+/// Если предпочтительный формат недоступен, берётся любой другой, для
+/// которого есть цепочка декодирования.
+pub fn best_match(modes: &[CapsMode], camera: &CameraConfig) -> Option<ModeChoice> {
+    let preferred = match &camera.source {
+        VideoSource::V4l2 { format, .. } => Some(format.clone()),
+        _ => None,
+    };
+    let target_area = camera.width as i64 * camera.height as i64;
+
+    modes
+        .iter()
+        .flat_map(|mode| {
+            mode.framerates
+                .candidates(camera.fps)
+                .into_iter()
+                .map(move |framerate| (mode, framerate))
+        })
+        .min_by_key(|(mode, framerate)| {
+            let format_penalty = match &preferred {
+                Some(PixelFormat::Raw) if mode.pixel_format().is_raw() => 0,
+                Some(format) if *format != mode.pixel_format() => 1,
+                _ => 0,
+            };
+            let area_diff = (mode.width as i64 * mode.height as i64 - target_area).abs();
+            let fps_diff = (fraction_to_fps(framerate) - camera.fps).abs();
+            (format_penalty, area_diff, fps_diff)
+        })
+        .map(|(mode, framerate)| ModeChoice {
+            format: mode.pixel_format(),
+            width: mode.width,
+            height: mode.height,
+            framerate,
+        })
+}
+
+/// Подгоняет настройки V4L2 камеры под режим, который она реально
+/// поддерживает. Если устройство не найдено, настройки остаются как есть.
+pub fn fit_camera_config(camera: &mut CameraConfig) {
+    let device = match &camera.source {
+        VideoSource::V4l2 { device, .. } => device.clone(),
+        _ => return,
+    };
+
+    let devices = match list_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            println!("Не удалось опросить устройства: {}", e);
+            return;
+        }
+    };
+
+    let Some(info) = devices
+        .iter()
+        .find(|d| d.path.as_deref() == Some(device.as_str()))
+    else {
+        println!(
+            "Устройство {} не найдено, используем настройки как есть",
+            device
+        );
+        return;
+    };
+
+    if !fit_to_modes(camera, &info.modes) {
+        println!("У устройства {} нет подходящих режимов", device);
+    }
+}
+
+/// Подгоняет настройки под лучший из режимов устройства. Возвращает false,
+/// если подходящего режима нет и настройки не изменились.
+pub fn fit_to_modes(camera: &mut CameraConfig, modes: &[CapsMode]) -> bool {
+    let Some(choice) = best_match(modes, camera) else {
+        return false;
+    };
+
+    let framerate = camera.framerate();
+    let VideoSource::V4l2 { format, .. } = &mut camera.source else {
+        return false;
+    };
+
+    if choice.width != camera.width
+        || choice.height != camera.height
+        || choice.framerate != framerate
+        || choice.format != *format
+    {
+        println!(
+            "Режим {}x{}@{} {:?} не поддерживается, используем {}x{}@{}/{} {:?}",
+            camera.width,
+            camera.height,
+            camera.fps,
+            format,
+            choice.width,
+            choice.height,
+            choice.framerate.numer(),
+            choice.framerate.denom(),
+            choice.format
+        );
+    }
+
+    *format = choice.format;
+    camera.width = choice.width;
+    camera.height = choice.height;
+    camera.fps = fraction_to_fps(&choice.framerate);
+    camera.exact_framerate = Some(choice.framerate);
+    true
+}
+
+/// Вывод для `--list-devices`
+pub fn print_devices() -> Result<(), Box<dyn Error>> {
+    let devices = list_devices()?;
+
+    if devices.is_empty() {
+        println!("Видео устройства не найдены");
+        return Ok(());
+    }
+
+    for info in devices {
+        println!(
+            "{} ({})",
+            info.name,
+            info.path.as_deref().unwrap_or("путь неизвестен")
+        );
+        for mode in &info.modes {
+            println!(
+                "    {} {}x{} @ {}",
+                mode.format,
+                mode.width,
+                mode.height,
+                mode.framerates.describe()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn caps(description: &str) -> Caps {
+        gstreamer::init().unwrap();
+        Caps::from_str(description).unwrap()
+    }
+
+    fn mode(format: &str, width: i32, height: i32, rates: &[i32]) -> CapsMode {
+        CapsMode {
+            format: String::from(format),
+            width,
+            height,
+            framerates: Framerates::List(rates.iter().map(|r| Fraction::new(*r, 1)).collect()),
+        }
+    }
+
+    fn range_mode(format: &str, width: i32, height: i32, min: i32, max: i32) -> CapsMode {
+        CapsMode {
+            format: String::from(format),
+            width,
+            height,
+            framerates: Framerates::Range {
+                min: Fraction::new(min, 1),
+                max: Fraction::new(max, 1),
+            },
+        }
+    }
+
+    fn camera(format: PixelFormat, width: i32, height: i32, fps: i32) -> CameraConfig {
+        CameraConfig {
+            width,
+            height,
+            fps,
+            source: VideoSource::V4l2 {
+                device: String::from("/dev/video0"),
+                format,
+            },
+            ..CameraConfig::default()
+        }
+    }
+
+    #[test]
+    fn parse_caps_splits_formats_and_rates() {
+        let modes = parse_caps(&caps(
+            "image/jpeg, width=(int)1280, height=(int)720, framerate=(fraction){ 30/1, 15/1 }; \
+             video/x-raw, format=(string){ YUY2, NV12 }, width=(int)640, height=(int)480, \
+             framerate=(fraction)30/1",
+        ));
+
+        assert_eq!(
+            modes,
+            vec![
+                mode("MJPEG", 1280, 720, &[30, 15]),
+                mode("YUY2", 640, 480, &[30]),
+                mode("NV12", 640, 480, &[30]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_caps_skips_ranges_and_unknown_media() {
+        let modes = parse_caps(&caps(
+            "video/x-raw, format=(string)YUY2, width=(int)[ 1, 1920 ], height=(int)[ 1, 1080 ]; \
+             audio/x-raw, rate=(int)48000; \
+             video/x-h264, width=(int)1920, height=(int)1080, framerate=(fraction)[ 1/1, 60/1 ]",
+        ));
+
+        assert_eq!(modes, vec![range_mode("H264", 1920, 1080, 1, 60)]);
+    }
+
+    #[test]
+    fn best_match_prefers_configured_format() {
+        let modes = [
+            mode("YUY2", 720, 480, &[25]),
+            mode("MJPEG", 1280, 720, &[30]),
+        ];
+
+        let choice = best_match(&modes, &camera(PixelFormat::Mjpeg, 720, 480, 25)).unwrap();
+        assert_eq!(
+            choice,
+            ModeChoice {
+                format: PixelFormat::Mjpeg,
+                width: 1280,
+                height: 720,
+                framerate: Fraction::new(30, 1),
+            }
+        );
+    }
+
+    #[test]
+    fn best_match_takes_nearest_size_and_rate() {
+        let modes = [
+            mode("MJPEG", 640, 480, &[30, 15]),
+            mode("MJPEG", 1920, 1080, &[30]),
+            mode("MJPEG", 800, 600, &[60, 20]),
+        ];
+
+        let choice = best_match(&modes, &camera(PixelFormat::Mjpeg, 720, 480, 25)).unwrap();
+        assert_eq!((choice.width, choice.height), (640, 480));
+        assert_eq!(choice.framerate, Fraction::new(30, 1));
+    }
+
+    #[test]
+    fn best_match_accepts_any_raw_format_for_raw() {
+        let modes = [
+            mode("MJPEG", 720, 480, &[25]),
+            mode("NV12", 720, 480, &[25]),
+        ];
+
+        let choice = best_match(&modes, &camera(PixelFormat::Raw, 720, 480, 25)).unwrap();
+        assert_eq!(choice.format, PixelFormat::Nv12);
+    }
+
+    #[test]
+    fn best_match_uses_config_fps_without_rates() {
+        let modes = [mode("YUY2", 720, 576, &[])];
+
+        let choice = best_match(&modes, &camera(PixelFormat::Yuy2, 720, 576, 25)).unwrap();
+        assert_eq!(choice.framerate, Fraction::new(25, 1));
+        assert!(best_match(&[], &camera(PixelFormat::Yuy2, 720, 576, 25)).is_none());
+    }
+
+    #[test]
+    fn best_match_takes_configured_fps_from_range() {
+        let modes = [range_mode("H264", 1920, 1080, 1, 60)];
+
+        let choice = best_match(&modes, &camera(PixelFormat::H264, 1920, 1080, 25)).unwrap();
+        assert_eq!(choice.framerate, Fraction::new(25, 1));
+
+        let choice = best_match(&modes, &camera(PixelFormat::H264, 1920, 1080, 120)).unwrap();
+        assert_eq!(choice.framerate, Fraction::new(60, 1));
+    }
+
+    #[test]
+    fn fit_to_modes_keeps_exact_ntsc_rate() {
+        let modes = [CapsMode {
+            format: String::from("YUY2"),
+            width: 720,
+            height: 480,
+            framerates: Framerates::List(vec![
+                Fraction::new(30000, 1001),
+                Fraction::new(15000, 1001),
+            ]),
+        }];
+        let mut config = camera(PixelFormat::Yuy2, 720, 480, 30);
+        assert!(fit_to_modes(&mut config, &modes));
+
+        assert_eq!(config.fps, 30);
+        assert_eq!(config.framerate(), Fraction::new(30000, 1001));
+        assert!(
+            config
+                .source
+                .pipeline_segment(config.width, config.height, config.framerate())
+                .contains("framerate=30000/1001")
+        );
+    }
+
+    #[test]
+    fn fit_to_modes_updates_camera() {
+        let mut config = camera(PixelFormat::Mjpeg, 1920, 1080, 60);
+        assert!(fit_to_modes(&mut config, &[mode("YUY2", 1280, 720, &[10])]));
+
+        assert_eq!((config.width, config.height, config.fps), (1280, 720, 10));
+        assert!(matches!(
+            config.source,
+            VideoSource::V4l2 {
+                format: PixelFormat::Yuy2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn fit_to_modes_keeps_camera_without_modes() {
+        let mut config = camera(PixelFormat::Mjpeg, 1920, 1080, 60);
+        assert!(!fit_to_modes(&mut config, &[]));
+        assert_eq!((config.width, config.height, config.fps), (1920, 1080, 60));
+    }
+}
@@ -0,0 +1,135 @@
+use crate::gst_utils::{
+    BranchCallback, buffer_running_time, detach_branch_with_eos, discard_tee_branch,
+    link_tee_branch,
+};
+use gstreamer::prelude::*;
+use gstreamer::{
+    Bin, BufferFlags, ClockTime, Element, Pad, PadProbeData, PadProbeId, PadProbeReturn,
+    PadProbeType, Pipeline, State,
+};
+use std::error::Error;
+use std::time::Duration;
+
+/// Кольцевой буфер уже сжатого видео для записи "последних N секунд".
+///
+/// Ветка на tee постоянно кодирует видео в очередь, которая выбрасывает
+/// старые данные. Выход очереди заблокирован пробой, пока не начнётся
+/// запись: тогда к нему подключается muxer, и накопленное видео, начиная
+/// с ключевого кадра, попадает в начало файла.
+pub struct DvrBuffer {
+    pipeline: Pipeline,
+    tee: Element,
+    bin: Bin,
+    src_pad: Pad,
+    block_probe: Option<PadProbeId>,
+    length: Duration,
+}
+
+impl DvrBuffer {
+    /// `encode` - описание кодирования от tee до сжатого потока
+    pub fn new(
+        pipeline: &Pipeline,
+        tee: &Element,
+        encode: &str,
+        length: Duration,
+        max_bytes: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let description = format!(
+            "{} ! queue leaky=downstream max-size-buffers=0 max-size-bytes={} max-size-time={}",
+            encode,
+            max_bytes,
+            length.as_nanos()
+        );
+        println!("Создаем DVR буфер: {}", description);
+
+        let bin = gstreamer::parse::bin_from_description(&description, true)?;
+        let src_pad = bin.static_pad("src").ok_or("У DVR буфера нет src pad")?;
+
+        // Без блокировки очередь отдала бы данные в неподключённый pad
+        let block_probe =
+            src_pad.add_probe(PadProbeType::BLOCK_DOWNSTREAM, |_, _| PadProbeReturn::Ok);
+
+        link_tee_branch(pipeline, tee, &bin)?;
+
+        Ok(Self {
+            pipeline: pipeline.clone(),
+            tee: tee.clone(),
+            bin,
+            src_pad,
+            block_probe,
+            length,
+        })
+    }
+
+    /// Принадлежит ли элемент буферу
+    pub fn owns(&self, object: &gstreamer::Object) -> bool {
+        object.has_as_ancestor(&self.bin)
+    }
+
+    /// Подключает ветку записи к выходу буфера. Данные до первого
+    /// ключевого кадра отбрасываются, чтобы файл начинался с него.
+    pub fn attach(&mut self, output: &Bin) -> Result<(), Box<dyn Error>> {
+        self.pipeline.add(output)?;
+        let sink_pad = output
+            .static_pad("sink")
+            .ok_or("У ветки записи нет sink pad")?;
+        self.src_pad.link(&sink_pad)?;
+        output.sync_state_with_parent()?;
+
+        // Буфер, который держала блокирующая проба, пришёл при создании
+        // буфера или в конце прошлой записи. Он и всё, что старше
+        // предзаписи, в файл не попадают.
+        let preroll = ClockTime::from_nseconds(self.length.as_nanos() as u64);
+        let oldest = self
+            .pipeline
+            .current_running_time()
+            .map(|now| now.saturating_sub(preroll));
+        self.src_pad
+            .add_probe(PadProbeType::BUFFER, move |pad, info| {
+                let Some(PadProbeData::Buffer(ref buffer)) = info.data else {
+                    return PadProbeReturn::Ok;
+                };
+                let stale = buffer_running_time(pad, buffer)
+                    .zip(oldest)
+                    .is_some_and(|(time, oldest)| time < oldest);
+                if stale || buffer.flags().contains(BufferFlags::DELTA_UNIT) {
+                    PadProbeReturn::Drop
+                } else {
+                    PadProbeReturn::Remove
+                }
+            });
+
+        if let Some(probe) = self.block_probe.take() {
+            self.src_pad.remove_probe(probe);
+        }
+
+        Ok(())
+    }
+
+    /// Завершает ветку записи через EOS. Буфер снова блокируется и
+    /// продолжает копить видео для следующей записи.
+    pub fn detach(&mut self, output: &Bin, callback: BranchCallback) {
+        self.block_probe =
+            detach_branch_with_eos(&self.pipeline, &self.src_pad, output, true, callback);
+    }
+
+    /// Убирает ветку записи без EOS, например после ошибки в ней
+    pub fn discard(&mut self, output: &Bin) {
+        if self.block_probe.is_none() {
+            self.block_probe = self
+                .src_pad
+                .add_probe(PadProbeType::BLOCK_DOWNSTREAM, |_, _| PadProbeReturn::Ok);
+        }
+        if let Some(sink_pad) = output.static_pad("sink") {
+            let _ = self.src_pad.unlink(&sink_pad);
+        }
+        let _ = output.set_state(State::Null);
+        let _ = self.pipeline.remove(output);
+    }
+
+    /// Отключает буфер от tee и удаляет его вместе с накопленным видео
+    pub fn remove(self) {
+        println!("Удаляем DVR буфер");
+        discard_tee_branch(&self.pipeline, &self.tee, &self.bin);
+    }
+}
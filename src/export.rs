@@ -0,0 +1,332 @@
+use crate::audio::AudioCodec;
+use crate::config::CameraConfig;
+use crate::library::media_file;
+use crate::profile::{Container, RecordingProfile};
+use crate::session::Session;
+use crate::sidecar::{self, ClipSource, Sidecar, SidecarData};
+use gstreamer::prelude::*;
+use gstreamer::{
+    ClockTime, Element, EventView, MessageType, MessageView, Pad, PadProbeData, PadProbeReturn,
+    PadProbeType, Pipeline, SeekFlags, SeekType, State,
+};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+/// Сколько ждать появления потоков исходного файла
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Сколько ждать записи фрагмента
+const EXPORT_TIMEOUT: ClockTime = ClockTime::from_seconds(600);
+
+/// Битрейт звука при перекодировании, кбит/с
+const AUDIO_BITRATE: u32 = 128;
+
+/// Что вырезать и как
+#[derive(Clone, Debug)]
+pub struct ExportRequest {
+    /// Файл записи или каталог сессии
+    pub source: PathBuf,
+    pub start: ClockTime,
+    pub end: ClockTime,
+    /// Перекодировать с точностью до кадра. Без перекодирования фрагмент
+    /// начинается с ключевого кадра перед `start`.
+    pub reencode: bool,
+    /// Камера и профиль для метаданных, если у записи их нет, и профиль
+    /// для перекодирования
+    pub camera: CameraConfig,
+    pub profile_name: String,
+    pub profile: RecordingProfile,
+}
+
+/// Вырезает фрагмент в новый файл рядом с исходной записью и пишет для
+/// него метаданные со ссылкой на источник. Возвращает путь фрагмента.
+pub fn export_clip(request: &ExportRequest) -> Result<PathBuf, Box<dyn Error>> {
+    if request.end <= request.start {
+        return Err("конец фрагмента должен быть после начала".into());
+    }
+
+    let source_data = sidecar::load(&request.source).unwrap_or_else(|| {
+        SidecarData::new(&request.camera, &request.profile_name, &request.profile)
+    });
+
+    // Перекодированный фрагмент пишется в контейнер профиля записи
+    let profile = if request.reencode && source_data.profile.validate().is_ok() {
+        source_data.profile.clone()
+    } else {
+        request.profile.clone()
+    };
+    let media = media_file(&request.source).ok_or("в записи нет файлов")?;
+    let container = if request.reencode {
+        profile.container
+    } else {
+        media
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(Container::from_extension)
+            .ok_or("неизвестный контейнер записи")?
+    };
+
+    let output = clip_path(&request.source, request.start, request.end, container);
+    println!(
+        "Экспорт фрагмента {} - {} из {} в {}",
+        request.start,
+        request.end,
+        request.source.display(),
+        output.display()
+    );
+
+    if let Err(e) = run_export(request, &profile, container, &output) {
+        let _ = fs::remove_file(&output);
+        return Err(e);
+    }
+
+    Sidecar::create_clip(
+        &output,
+        source_data,
+        ClipSource {
+            source: request.source.clone(),
+            start_ms: request.start.mseconds(),
+            end_ms: request.end.mseconds(),
+            reencoded: request.reencode,
+        },
+    );
+    println!("Фрагмент сохранён: {}", output.display());
+    Ok(output)
+}
+
+/// Имя фрагмента: имя записи и границы, при совпадении добавляется номер
+fn clip_path(source: &Path, start: ClockTime, end: ClockTime, container: Container) -> PathBuf {
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let base = format!("{}_clip_{}-{}", stem, time_name(start), time_name(end));
+
+    let mut attempt = 1;
+    loop {
+        let name = match attempt {
+            1 => format!("{}.{}", base, container.extension()),
+            n => format!("{}_{}.{}", base, n, container.extension()),
+        };
+        let path = source.with_file_name(name);
+        if !path.exists() {
+            return path;
+        }
+        attempt += 1;
+    }
+}
+
+fn time_name(time: ClockTime) -> String {
+    let secs = time.seconds();
+    format!("{:02}-{:02}-{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Читает исходную запись с перемоткой к началу фрагмента и остановкой на
+/// его конце. Без перекодирования сжатые потоки идут в muxer как есть.
+fn run_export(
+    request: &ExportRequest,
+    profile: &RecordingProfile,
+    container: Container,
+    output: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let source = if request.source.is_dir() {
+        let session = Session::open(&request.source)?;
+        format!(
+            "splitmuxsrc name=src location=\"{}\"",
+            session.segment_glob()
+        )
+    } else {
+        format!(
+            "filesrc location=\"{}\" ! {} name=src",
+            request.source.display(),
+            if request.reencode {
+                "decodebin"
+            } else {
+                "parsebin"
+            }
+        )
+    };
+    // filesink не ждёт preroll: до перемотки данные в muxer не пропускаются
+    let description = format!(
+        "{} {} name=mux ! filesink location=\"{}\" async=false",
+        source,
+        container.muxer_element(),
+        output.display()
+    );
+    println!("Экспорт: {}", description);
+
+    let pipeline = gstreamer::parse::launch(&description)?
+        .dynamic_cast::<Pipeline>()
+        .map_err(|_| "не удалось создать pipeline")?;
+    let src = pipeline.by_name("src").ok_or("нет источника")?;
+    let mux = pipeline.by_name("mux").ok_or("нет muxer")?;
+
+    let link = Arc::new(StreamLinker {
+        pipeline: pipeline.downgrade(),
+        mux,
+        profile: profile.clone(),
+        container,
+        reencode: request.reencode,
+    });
+    src.connect_pad_added({
+        let link = link.clone();
+        move |_, pad| {
+            hold_until_seek(pad);
+            if let Err(e) = link.link(pad) {
+                println!("Не удалось подключить поток {}: {}", pad.name(), e);
+            }
+        }
+    });
+    let (sender, receiver) = channel();
+    src.connect_no_more_pads(move |_| {
+        let _ = sender.send(());
+    });
+
+    pipeline.set_state(State::Paused)?;
+    let result = receiver
+        .recv_timeout(OPEN_TIMEOUT)
+        .map_err(|_| Box::<dyn Error>::from("в записи не найдено потоков"))
+        .and_then(|_| seek_and_wait(&pipeline, request));
+
+    pipeline.set_state(State::Null)?;
+    result
+}
+
+fn seek_and_wait(pipeline: &Pipeline, request: &ExportRequest) -> Result<(), Box<dyn Error>> {
+    let flags = if request.reencode {
+        SeekFlags::FLUSH | SeekFlags::ACCURATE
+    } else {
+        SeekFlags::FLUSH | SeekFlags::KEY_UNIT | SeekFlags::SNAP_BEFORE
+    };
+    pipeline.seek(
+        1.0,
+        flags,
+        SeekType::Set,
+        request.start,
+        SeekType::Set,
+        request.end,
+    )?;
+    pipeline.set_state(State::Playing)?;
+
+    let bus = pipeline.bus().ok_or("нет шины pipeline")?;
+    let msg = bus
+        .timed_pop_filtered(EXPORT_TIMEOUT, &[MessageType::Eos, MessageType::Error])
+        .ok_or("экспорт не завершился вовремя")?;
+    match msg.view() {
+        MessageView::Error(err) => Err(err.error().to_string().into()),
+        _ => Ok(()),
+    }
+}
+
+/// Не пропускает данные и EOS потока, пока через него не пройдёт
+/// перемотка. Так в файл не попадает начало записи, прочитанное до неё.
+fn hold_until_seek(pad: &Pad) {
+    let seeked = AtomicBool::new(false);
+    pad.add_probe(
+        PadProbeType::BUFFER
+            | PadProbeType::BUFFER_LIST
+            | PadProbeType::EVENT_BOTH
+            | PadProbeType::EVENT_FLUSH,
+        move |_, info| {
+            if seeked.load(Ordering::SeqCst) {
+                return PadProbeReturn::Remove;
+            }
+            match &info.data {
+                Some(PadProbeData::Event(event)) => match event.view() {
+                    EventView::FlushStop(_) => {
+                        seeked.store(true, Ordering::SeqCst);
+                        PadProbeReturn::Ok
+                    }
+                    EventView::Eos(_) => PadProbeReturn::Drop,
+                    _ => PadProbeReturn::Ok,
+                },
+                Some(PadProbeData::Buffer(_)) | Some(PadProbeData::BufferList(_)) => {
+                    PadProbeReturn::Drop
+                }
+                _ => PadProbeReturn::Ok,
+            }
+        },
+    );
+}
+
+/// Подключает потоки источника к muxer, при перекодировании - через
+/// кодировщики. Потоки, которые контейнер не хранит, выбрасываются.
+struct StreamLinker {
+    pipeline: gstreamer::glib::WeakRef<Pipeline>,
+    mux: Element,
+    profile: RecordingProfile,
+    container: Container,
+    reencode: bool,
+}
+
+impl StreamLinker {
+    fn link(self: &Arc<Self>, pad: &Pad) -> Result<(), Box<dyn Error>> {
+        let pipeline = self.pipeline.upgrade().ok_or("pipeline уже удалён")?;
+        let caps = pad.current_caps().unwrap_or_else(|| pad.query_caps(None));
+        let media = caps
+            .structure(0)
+            .map(|s| s.name().to_string())
+            .unwrap_or_default();
+
+        // Сжатый поток от splitmuxsrc сначала декодируется
+        if self.reencode && !media.ends_with("/x-raw") {
+            let decodebin = gstreamer::ElementFactory::make("decodebin").build()?;
+            decodebin.connect_pad_added({
+                let link = self.clone();
+                move |_, pad| {
+                    if let Err(e) = link.link(pad) {
+                        println!("Не удалось подключить поток {}: {}", pad.name(), e);
+                    }
+                }
+            });
+            return link_to_element(&pipeline, pad, &decodebin);
+        }
+
+        let encode = match media.as_str() {
+            "video/x-raw" => Some(self.profile.encode_description(false)),
+            "audio/x-raw" => {
+                let codec = if self.container.supports_audio(AudioCodec::Aac) {
+                    AudioCodec::Aac
+                } else {
+                    AudioCodec::Opus
+                };
+                Some(codec.encode_description(AUDIO_BITRATE))
+            }
+            _ => None,
+        };
+
+        let stream_pad = match encode {
+            Some(description) => {
+                let bin = gstreamer::parse::bin_from_description(&description, true)?;
+                link_to_element(&pipeline, pad, bin.upcast_ref())?;
+                bin.static_pad("src").ok_or("у кодировщика нет выхода")?
+            }
+            None => pad.clone(),
+        };
+
+        let Some(mux_pad) = self.mux.compatible_pad(&stream_pad, None) else {
+            println!("Поток {} не поддерживается контейнером, пропускаем", media);
+            let fakesink = gstreamer::ElementFactory::make("fakesink")
+                .property("async", false)
+                .build()?;
+            return link_to_element(&pipeline, &stream_pad, &fakesink);
+        };
+        stream_pad.link(&mux_pad)?;
+        Ok(())
+    }
+}
+
+/// Добавляет элемент в pipeline и подключает к нему `pad`
+fn link_to_element(
+    pipeline: &Pipeline,
+    pad: &Pad,
+    element: &Element,
+) -> Result<(), Box<dyn Error>> {
+    pipeline.add(element)?;
+    element.sync_state_with_parent()?;
+    let sink_pad = element.static_pad("sink").ok_or("у элемента нет входа")?;
+    pad.link(&sink_pad)?;
+    Ok(())
+}
@@ -1,21 +1,35 @@
+use gstreamer::glib;
 use gstreamer::prelude::*;
 use gstreamer::{
-    Bin, Bus, Element, MessageView, Pipeline, State,
+    Bin, Bus, ClockTime, Element, ElementFlags, EventType, MessageView, Pad, PadProbeData,
+    PadProbeId, PadProbeReturn, PadProbeType, Pipeline, State,
 };
 use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-/// Отключает ветку от tee
+/// Сколько ждать EOS от ветки, прежде чем убрать её принудительно
+const BRANCH_EOS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Вызывается, когда ветка убрана. Аргумент - дошёл ли EOS до её конца.
+pub type BranchCallback = Box<dyn FnOnce(bool) + Send>;
+
+/// Отключает ветку от tee, не останавливая остальной pipeline.
+///
+/// Src pad tee блокируется, ветка отсоединяется и получает EOS, чтобы muxer
+/// дописал файл. Когда EOS доходит до sink ветки, она переводится в Null,
+/// удаляется из pipeline, а pad tee освобождается. Это и вызов `callback`
+/// происходят в главном потоке. Аргумент `callback` - дошёл ли EOS до конца
+/// ветки, то есть завершён ли файл. См. `detach_branch_with_eos`.
 pub fn unlink_tee_branch(
     pipeline: &Pipeline,
     tee: &Element,
     branch: &Bin,
-    callback: Box<dyn Fn()>,
+    callback: BranchCallback,
 ) {
-    // Переводим branch в состояние Null
-    let _ = branch.set_state(State::Null);
-
-    // Получаем все src pads от tee
-    if let Some(tee_src_pad) = tee.pads().into_iter().find(|p| {
+    // Находим src pad tee, к которому подключена ветка
+    let tee_src_pad = tee.pads().into_iter().find(|p| {
         p.direction() == gstreamer::PadDirection::Src
             && p.is_linked()
             && p.peer().map_or(false, |peer| {
@@ -25,20 +39,219 @@ pub fn unlink_tee_branch(
                     false
                 }
             })
-    }) {
-        // Отключаем pad
-        if let Some(peer) = tee_src_pad.peer() {
-            let _ = tee_src_pad.unlink(&peer);
+    });
+
+    let Some(tee_src_pad) = tee_src_pad else {
+        // Ветка уже не подключена, просто убираем её
+        let _ = branch.set_state(State::Null);
+        let _ = pipeline.remove(branch);
+        callback(false);
+        return;
+    };
+
+    let tee = tee.clone();
+    let released_pad = tee_src_pad.clone();
+    detach_branch_with_eos(
+        pipeline,
+        &tee_src_pad,
+        branch,
+        false,
+        Box::new(move |eos_reached| {
+            tee.release_request_pad(&released_pad);
+            callback(eos_reached);
+        }),
+    );
+}
+
+/// Отсоединяет ветку от `src_pad` и завершает её через EOS.
+///
+/// `src_pad` блокируется, ветка отсоединяется и получает EOS. Когда EOS
+/// доходит до всех sink ветки (или по таймауту), ветка переводится в Null и
+/// удаляется из pipeline в главном потоке, затем вызывается `callback`.
+/// С `keep_blocked` pad остаётся заблокированным, и возвращается id пробы,
+/// которую нужно снять, чтобы снова пропустить данные.
+pub fn detach_branch_with_eos(
+    pipeline: &Pipeline,
+    src_pad: &Pad,
+    branch: &Bin,
+    keep_blocked: bool,
+    callback: BranchCallback,
+) -> Option<PadProbeId> {
+    // Завершение выполняется один раз: по EOS или по таймауту
+    let finalize: Arc<Mutex<Option<BranchCallback>>> = Arc::new(Mutex::new(Some({
+        let pipeline = pipeline.clone();
+        let branch = branch.clone();
+        Box::new(move |eos_reached| {
+            let _ = branch.set_state(State::Null);
+            let _ = pipeline.remove(&branch);
+            callback(eos_reached);
+        })
+    })));
+
+    let run_finalize = {
+        let finalize = finalize.clone();
+        move |eos_reached: bool| {
+            if let Some(finalize) = finalize.lock().unwrap().take() {
+                glib::MainContext::default().invoke(move || finalize(eos_reached));
+            }
         }
-        // Освобождаем pad
-        tee.release_request_pad(&tee_src_pad);
+    };
+
+    // Ждём EOS на входе каждого sink ветки, в том числе вложенных в другие
+    // bin, как filesink внутри splitmuxsink
+    let sinks: Vec<Element> = branch
+        .iterate_recurse()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.element_flags().contains(ElementFlags::SINK) && !e.is::<Bin>())
+        .collect();
+    let pending_sinks = Arc::new(AtomicUsize::new(sinks.len()));
+
+    for sink in &sinks {
+        let Some(sink_pad) = sink.static_pad("sink") else {
+            pending_sinks.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        };
+        let pending_sinks = pending_sinks.clone();
+        let run_finalize = run_finalize.clone();
+        sink_pad.add_probe(PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+            match info.data {
+                Some(PadProbeData::Event(ref event)) if event.type_() == EventType::Eos => {
+                    if pending_sinks.fetch_sub(1, Ordering::SeqCst) == 1 {
+                        println!("EOS дошёл до конца ветки");
+                        run_finalize(true);
+                    }
+                    PadProbeReturn::Remove
+                }
+                _ => PadProbeReturn::Ok,
+            }
+        });
     }
 
-    // Удаляем branch из pipeline
+    // Блокируем поток данных в ветку и отправляем EOS уже без src_pad
+    let eos_sent = Arc::new(AtomicBool::new(false));
+    let branch_sink_pad = branch.static_pad("sink");
+    let block_probe = src_pad.add_probe(PadProbeType::BLOCK_DOWNSTREAM, move |pad, _| {
+        if !eos_sent.swap(true, Ordering::SeqCst)
+            && let Some(sink_pad) = &branch_sink_pad
+        {
+            let _ = pad.unlink(sink_pad);
+            sink_pad.send_event(gstreamer::event::Eos::new());
+        }
+
+        if keep_blocked {
+            PadProbeReturn::Ok
+        } else {
+            PadProbeReturn::Remove
+        }
+    });
+
+    // Ветка с ошибкой может так и не пропустить EOS
+    glib::timeout_add_once(BRANCH_EOS_TIMEOUT, move || {
+        if finalize.lock().unwrap().is_some() {
+            println!(
+                "Ветка не завершилась за {:?}, убираем принудительно",
+                BRANCH_EOS_TIMEOUT
+            );
+        }
+        run_finalize(false);
+    });
+
+    if keep_blocked { block_probe } else { None }
+}
+
+/// Убирает ветку без EOS: когда файл не нужно завершать или после ошибки
+/// его уже не завершить корректно
+pub fn discard_tee_branch(pipeline: &Pipeline, tee: &Element, branch: &Bin) {
+    if let Some(sink_pad) = branch.static_pad("sink") {
+        release_tee_input(tee, &sink_pad);
+    }
+    let _ = branch.set_state(State::Null);
     let _ = pipeline.remove(branch);
+}
 
-    // Вызываем callback
-    callback();
+/// Отсоединяет вход ветки от tee и освобождает pad tee
+pub fn release_tee_input(tee: &Element, sink_pad: &Pad) {
+    if let Some(tee_src_pad) = sink_pad.peer() {
+        let _ = tee_src_pad.unlink(sink_pad);
+        tee.release_request_pad(&tee_src_pad);
+    }
+}
+
+/// Завершает дополнительный вход ветки, например звук: когда pad tee
+/// свободен, вход отсоединяется и получает EOS. Если данных нет, это
+/// происходит сразу, так что muxer не ждёт остановившийся источник.
+pub fn end_tee_input(tee: &Element, sink_pad: &Pad) {
+    let Some(tee_src_pad) = sink_pad.peer() else {
+        sink_pad.send_event(gstreamer::event::Eos::new());
+        return;
+    };
+
+    let tee = tee.clone();
+    let sink_pad = sink_pad.clone();
+    tee_src_pad.add_probe(PadProbeType::IDLE, move |pad, _| {
+        let _ = pad.unlink(&sink_pad);
+        sink_pad.send_event(gstreamer::event::Eos::new());
+
+        // Pad нельзя освобождать из его же потока
+        let tee = tee.clone();
+        let pad = pad.clone();
+        glib::MainContext::default().invoke(move || tee.release_request_pad(&pad));
+        PadProbeReturn::Remove
+    });
+}
+
+/// Запоминает running-time первого буфера, пришедшего на вход ветки.
+/// Пробу нужно ставить до подключения ветки.
+pub fn first_buffer_running_time(branch: &Bin) -> Arc<Mutex<Option<ClockTime>>> {
+    let first = Arc::new(Mutex::new(None));
+    let Some(sink_pad) = branch.static_pad("sink") else {
+        return first;
+    };
+
+    sink_pad.add_probe(PadProbeType::BUFFER, {
+        let first = first.clone();
+        move |pad, info| {
+            let Some(PadProbeData::Buffer(ref buffer)) = info.data else {
+                return PadProbeReturn::Ok;
+            };
+            *first.lock().unwrap() = buffer_running_time(pad, buffer);
+            PadProbeReturn::Remove
+        }
+    });
+    first
+}
+
+/// Запоминает running-time конца последнего буфера на входе ветки
+pub fn last_buffer_end_running_time(branch: &Bin) -> Arc<Mutex<Option<ClockTime>>> {
+    let last = Arc::new(Mutex::new(None));
+    let Some(sink_pad) = branch.static_pad("sink") else {
+        return last;
+    };
+
+    sink_pad.add_probe(PadProbeType::BUFFER, {
+        let last = last.clone();
+        move |pad, info| {
+            if let Some(PadProbeData::Buffer(ref buffer)) = info.data
+                && let Some(start) = buffer_running_time(pad, buffer)
+            {
+                *last.lock().unwrap() = Some(start + buffer.duration().unwrap_or(ClockTime::ZERO));
+            }
+            PadProbeReturn::Ok
+        }
+    });
+    last
+}
+
+/// running-time буфера по сегменту, пришедшему на `pad`
+pub fn buffer_running_time(pad: &Pad, buffer: &gstreamer::BufferRef) -> Option<ClockTime> {
+    pad.sticky_event::<gstreamer::event::Segment>(0)
+        .and_then(|event| {
+            event
+                .segment()
+                .downcast_ref::<ClockTime>()
+                .and_then(|segment| segment.to_running_time(buffer.pts()))
+        })
 }
 
 /// Подключает ветку к tee
@@ -107,3 +320,114 @@ pub fn dispatch_messages(bus: &Bus, pipeline: &Pipeline) -> bool {
     true
 }
 
+/// Главный контекст glib для тестов. Тесты идут параллельно, а контекст
+/// по умолчанию может захватить только один поток.
+#[cfg(test)]
+pub fn with_default_context(test: impl FnOnce(&glib::MainContext)) {
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let context = glib::MainContext::default();
+    let _guard = context.acquire().unwrap();
+    test(&context);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Instant;
+
+    /// Считает буферы, дошедшие до элемента
+    fn count_buffers(element: &Element) -> Arc<AtomicUsize> {
+        let count = Arc::new(AtomicUsize::new(0));
+        element
+            .static_pad("sink")
+            .unwrap()
+            .add_probe(PadProbeType::BUFFER, {
+                let count = count.clone();
+                move |_, _| {
+                    count.fetch_add(1, Ordering::SeqCst);
+                    PadProbeReturn::Ok
+                }
+            });
+        count
+    }
+
+    fn run_until(context: &glib::MainContext, timeout: Duration, done: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !done() && start.elapsed() < timeout {
+            while context.iteration(false) {}
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn display_keeps_running_while_branch_stops() {
+        gstreamer::init().unwrap();
+        with_default_context(|context| {
+            let pipeline = gstreamer::parse::launch(
+                "videotestsrc is-live=true ! video/x-raw,width=320,height=240,framerate=30/1 ! \
+                tee name=t allow-not-linked=true ! \
+                queue max-size-buffers=2 leaky=downstream ! fakesink name=display sync=false",
+            )
+            .unwrap()
+            .dynamic_cast::<Pipeline>()
+            .unwrap();
+            let tee = pipeline.by_name("t").unwrap();
+            let display = count_buffers(&pipeline.by_name("display").unwrap());
+            pipeline.set_state(State::Playing).unwrap();
+            run_until(context, Duration::from_secs(5), || {
+                display.load(Ordering::SeqCst) > 10
+            });
+
+            // Медленная ветка, как запись: EOS доходит до конца не сразу
+            let branch = gstreamer::parse::bin_from_description(
+                "queue max-size-buffers=5 ! identity sleep-time=100000 ! fakesink name=rec",
+                true,
+            )
+            .unwrap();
+            let recorded = count_buffers(&branch.by_name("rec").unwrap());
+            link_tee_branch(&pipeline, &tee, &branch).unwrap();
+            run_until(context, Duration::from_secs(5), || {
+                recorded.load(Ordering::SeqCst) > 5
+            });
+            assert!(recorded.load(Ordering::SeqCst) > 5);
+
+            let (sender, receiver) = channel();
+            let before_stop = display.load(Ordering::SeqCst);
+            unlink_tee_branch(
+                &pipeline,
+                &tee,
+                &branch,
+                Box::new(move |eos_reached| sender.send(eos_reached).unwrap()),
+            );
+
+            let result = std::cell::Cell::new(None);
+            run_until(context, Duration::from_secs(10), || {
+                if let Ok(eos_reached) = receiver.try_recv() {
+                    result.set(Some(eos_reached));
+                }
+                result.get().is_some()
+            });
+            let during_stop = display.load(Ordering::SeqCst) - before_stop;
+
+            assert_eq!(result.get(), Some(true), "EOS не дошёл до конца ветки");
+            assert!(
+                during_stop >= 5,
+                "окно получило {} кадров за остановку",
+                during_stop
+            );
+            assert!(branch.parent().is_none());
+            assert_eq!(tee.src_pads().len(), 1);
+
+            // И после остановки ветки
+            let after_stop = display.load(Ordering::SeqCst);
+            run_until(context, Duration::from_secs(5), || {
+                display.load(Ordering::SeqCst) > after_stop + 10
+            });
+            assert!(display.load(Ordering::SeqCst) > after_stop + 10);
+
+            pipeline.set_state(State::Null).unwrap();
+        });
+    }
+}
@@ -0,0 +1,247 @@
+use crate::session::Session;
+use crate::sidecar::{self, SidecarData};
+use crate::storage::{StorageManager, StoredRecording};
+use gstreamer::prelude::*;
+use gstreamer::{ClockTime, MessageType, MessageView, Pipeline, State};
+use gtk4::glib;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+/// Ширина миниатюры, высота по пропорциям кадра
+const THUMBNAIL_WIDTH: i32 = 160;
+
+/// Сколько ждать первый кадр записи
+const THUMBNAIL_TIMEOUT: ClockTime = ClockTime::from_seconds(10);
+
+/// Запись в библиотеке
+#[derive(Clone, Debug)]
+pub struct LibraryItem {
+    pub recording: StoredRecording,
+    pub sidecar: Option<SidecarData>,
+}
+
+/// Миниатюра и длительность записи
+#[derive(Clone, Debug)]
+pub struct Preview {
+    pub path: PathBuf,
+    pub thumbnail: Option<PathBuf>,
+    pub duration: Option<Duration>,
+}
+
+/// Что хранится в кэше рядом с миниатюрой
+#[derive(Default, Serialize, Deserialize)]
+struct CachedInfo {
+    duration_ms: Option<u64>,
+}
+
+/// Записи каталога с метаданными, новые сверху
+pub fn scan(storage: &StorageManager) -> Vec<LibraryItem> {
+    storage
+        .recordings()
+        .into_iter()
+        .rev()
+        .map(|recording| LibraryItem {
+            sidecar: sidecar::load(&recording.path),
+            recording,
+        })
+        .collect()
+}
+
+/// Файл, по которому строится миниатюра: сама запись или первый сегмент
+/// сессии
+pub fn media_file(path: &Path) -> Option<PathBuf> {
+    if !path.is_dir() {
+        return Some(path.to_path_buf());
+    }
+    Session::open(path).ok()?.segment_paths().into_iter().next()
+}
+
+/// Готовит миниатюры в фоне. Миниатюры кэшируются на диске по пути,
+/// размеру и времени изменения записи, так что повторно строятся только
+/// для новых или изменённых файлов.
+pub struct ThumbnailGenerator {
+    runtime: Runtime,
+    cache_dir: PathBuf,
+}
+
+impl ThumbnailGenerator {
+    pub fn new() -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .max_blocking_threads(2)
+            .thread_name("thumbnails")
+            .enable_all()
+            .build()?;
+        let cache_dir = glib::user_cache_dir().join("ncy_gtk").join("thumbnails");
+        fs::create_dir_all(&cache_dir)?;
+        Ok(Self { runtime, cache_dir })
+    }
+
+    /// Запускает подготовку превью. Готовые превью приходят в канал по одному,
+    /// если получатель закрыт, оставшиеся не строятся.
+    pub fn generate(&self, recordings: Vec<StoredRecording>) -> UnboundedReceiver<Preview> {
+        let (sender, receiver) = unbounded_channel();
+        let cache_dir = self.cache_dir.clone();
+
+        self.runtime.spawn(async move {
+            for recording in recordings {
+                let cache_dir = cache_dir.clone();
+                let preview =
+                    tokio::task::spawn_blocking(move || preview(&cache_dir, &recording)).await;
+                match preview {
+                    Ok(preview) => {
+                        if sender.send(preview).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => println!("Ошибка подготовки миниатюры: {}", e),
+                }
+            }
+        });
+        receiver
+    }
+}
+
+fn preview(cache_dir: &Path, recording: &StoredRecording) -> Preview {
+    let key = cache_key(recording);
+    let thumbnail = cache_dir.join(format!("{}.jpg", key));
+    let info_path = cache_dir.join(format!("{}.json", key));
+
+    let cached = fs::read_to_string(&info_path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<CachedInfo>(&text).ok());
+
+    let info = match cached {
+        Some(info) => info,
+        None => {
+            let info = build_preview(&recording.path, &thumbnail);
+            if let Ok(json) = serde_json::to_string(&info) {
+                let _ = fs::write(&info_path, json);
+            }
+            info
+        }
+    };
+
+    Preview {
+        path: recording.path.clone(),
+        thumbnail: thumbnail.exists().then_some(thumbnail),
+        duration: info.duration_ms.map(Duration::from_millis),
+    }
+}
+
+fn build_preview(path: &Path, thumbnail: &Path) -> CachedInfo {
+    let Some(media) = media_file(path) else {
+        return CachedInfo::default();
+    };
+
+    let duration_ms = match render_thumbnail(&media, thumbnail) {
+        Ok(duration) => duration.map(ClockTime::mseconds),
+        Err(e) => {
+            println!("Не удалось построить миниатюру {}: {}", path.display(), e);
+            let _ = fs::remove_file(thumbnail);
+            None
+        }
+    };
+
+    // У сессии длительность всех сегментов, а не только первого
+    let duration_ms = if path.is_dir() {
+        Session::open(path).ok().and_then(|s| s.duration_ms())
+    } else {
+        duration_ms
+    };
+    CachedInfo { duration_ms }
+}
+
+/// Кодирует первый кадр файла в JPEG и возвращает длительность файла
+fn render_thumbnail(input: &Path, output: &Path) -> Result<Option<ClockTime>, Box<dyn Error>> {
+    let description = format!(
+        "filesrc location=\"{}\" ! decodebin name=dec \
+        videoconvert name=conv ! videoscale ! \
+        video/x-raw,width={},pixel-aspect-ratio=1/1 ! \
+        jpegenc snapshot=true ! filesink location=\"{}\"",
+        input.display(),
+        THUMBNAIL_WIDTH,
+        output.display()
+    );
+
+    let pipeline = gstreamer::parse::launch(&description)?
+        .dynamic_cast::<Pipeline>()
+        .map_err(|_| "не удалось создать pipeline")?;
+    let decodebin = pipeline.by_name("dec").ok_or("нет decodebin")?;
+    let convert = pipeline.by_name("conv").ok_or("нет videoconvert")?;
+
+    // Видео идёт в кодировщик, остальные потоки выбрасываются
+    decodebin.connect_pad_added({
+        let pipeline = pipeline.downgrade();
+        move |_, pad| {
+            let is_video = pad
+                .current_caps()
+                .and_then(|caps| caps.structure(0).map(|s| s.name().starts_with("video/")))
+                .unwrap_or(false);
+            let video_sink = convert.static_pad("sink").filter(|p| !p.is_linked());
+
+            let sink_pad = match video_sink {
+                Some(sink_pad) if is_video => sink_pad,
+                _ => {
+                    let Some(pipeline) = pipeline.upgrade() else {
+                        return;
+                    };
+                    let Ok(fakesink) = gstreamer::ElementFactory::make("fakesink").build() else {
+                        return;
+                    };
+                    let _ = pipeline.add(&fakesink);
+                    let _ = fakesink.sync_state_with_parent();
+                    let Some(sink_pad) = fakesink.static_pad("sink") else {
+                        return;
+                    };
+                    sink_pad
+                }
+            };
+            if let Err(e) = pad.link(&sink_pad) {
+                println!("Не удалось подключить поток {}: {}", pad.name(), e);
+            }
+        }
+    });
+
+    let bus = pipeline.bus().ok_or("нет шины pipeline")?;
+    let wait = |types: &[MessageType]| -> Result<(), Box<dyn Error>> {
+        let msg = bus
+            .timed_pop_filtered(THUMBNAIL_TIMEOUT, types)
+            .ok_or("файл не читается вовремя")?;
+        match msg.view() {
+            MessageView::Error(err) => Err(err.error().to_string().into()),
+            _ => Ok(()),
+        }
+    };
+
+    // Длительность известна после preroll, а кадр записывается только в PLAYING
+    let result = pipeline
+        .set_state(State::Paused)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|_| wait(&[MessageType::AsyncDone, MessageType::Error]))
+        .map(|_| pipeline.query_duration::<ClockTime>())
+        .and_then(|duration| {
+            pipeline.set_state(State::Playing)?;
+            wait(&[MessageType::Eos, MessageType::Error])?;
+            Ok(duration)
+        });
+
+    pipeline.set_state(State::Null)?;
+    result
+}
+
+fn cache_key(recording: &StoredRecording) -> String {
+    let mut hasher = DefaultHasher::new();
+    recording.path.hash(&mut hasher);
+    recording.size.hash(&mut hasher);
+    recording.modified.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
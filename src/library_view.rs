@@ -0,0 +1,356 @@
+use crate::library::{self, LibraryItem, Preview, ThumbnailGenerator};
+use crate::naming::FileNamer;
+use crate::storage::{self, StorageManager};
+use gtk4::gdk::Texture;
+use gtk4::prelude::*;
+use gtk4::{
+    Box as GtkBox, Button, Entry, Label, ListBox, Orientation, Picture, ScrolledWindow, glib,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+const MB: u64 = 1024 * 1024;
+
+/// Обработчик кнопки "Смотреть"
+type PlayCallback = Rc<dyn Fn(&Path)>;
+
+/// Виджеты строки, которые обновляются, когда готово превью
+struct RowWidgets {
+    thumbnail: Picture,
+    details: Label,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Экран со списком записей из каталога `CameraConfig.path`
+pub struct LibraryView {
+    root: GtkBox,
+    list: ListBox,
+    status: Label,
+    storage: StorageManager,
+    namer: Rc<FileNamer>,
+    thumbnails: Option<ThumbnailGenerator>,
+    /// Пути текущей и дописываемой записей, их нельзя удалять и переименовывать
+    active: Box<dyn Fn() -> Vec<PathBuf>>,
+    rows: RefCell<HashMap<PathBuf, RowWidgets>>,
+    /// Открытие записи для просмотра
+    on_play: RefCell<Option<PlayCallback>>,
+    /// Номер обновления списка, превью от прошлых обновлений не нужны
+    generation: Cell<u64>,
+}
+
+impl LibraryView {
+    pub fn new(
+        storage: StorageManager,
+        namer: Rc<FileNamer>,
+        active: impl Fn() -> Vec<PathBuf> + 'static,
+    ) -> Rc<Self> {
+        let root = GtkBox::new(Orientation::Vertical, 5);
+        root.add_css_class("library");
+        root.set_hexpand(true);
+        root.set_vexpand(true);
+
+        let status = Label::new(None);
+        status.add_css_class("library-status");
+        root.append(&status);
+
+        let list = ListBox::new();
+        list.set_selection_mode(gtk4::SelectionMode::None);
+        let scrolled = ScrolledWindow::new();
+        scrolled.set_vexpand(true);
+        scrolled.set_child(Some(&list));
+        root.append(&scrolled);
+
+        let thumbnails = match ThumbnailGenerator::new() {
+            Ok(generator) => Some(generator),
+            Err(e) => {
+                println!("Миниатюры недоступны: {}", e);
+                None
+            }
+        };
+
+        Rc::new(Self {
+            root,
+            list,
+            status,
+            storage,
+            namer,
+            thumbnails,
+            active: Box::new(active),
+            rows: RefCell::new(HashMap::new()),
+            on_play: RefCell::new(None),
+            generation: Cell::new(0),
+        })
+    }
+
+    pub fn widget(&self) -> &GtkBox {
+        &self.root
+    }
+
+    /// Кнопка "Смотреть" в строке записи
+    pub fn connect_play(&self, callback: impl Fn(&Path) + 'static) {
+        *self.on_play.borrow_mut() = Some(Rc::new(callback));
+    }
+
+    /// Перечитывает каталог и запускает подготовку миниатюр
+    pub fn refresh(self: &Rc<Self>) {
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+
+        while let Some(row) = self.list.first_child() {
+            self.list.remove(&row);
+        }
+        self.rows.borrow_mut().clear();
+
+        let items = library::scan(&self.storage);
+        let total: u64 = items.iter().map(|item| item.recording.size).sum();
+        self.status.set_label(&format!(
+            "Записей: {}, {} МБ, свободно {} МБ",
+            items.len(),
+            total / MB,
+            self.storage.free_space().unwrap_or(0) / MB
+        ));
+
+        let active = (self.active)();
+        for item in &items {
+            let is_active = active.contains(&item.recording.path);
+            self.list.append(&self.build_row(item, is_active));
+        }
+
+        let Some(thumbnails) = &self.thumbnails else {
+            return;
+        };
+        let mut previews =
+            thumbnails.generate(items.into_iter().map(|item| item.recording).collect());
+        let view = Rc::downgrade(self);
+        glib::spawn_future_local(async move {
+            while let Some(preview) = previews.recv().await {
+                let Some(view) = view.upgrade() else {
+                    break;
+                };
+                if view.generation.get() != generation {
+                    break;
+                }
+                view.show_preview(&preview);
+            }
+        });
+    }
+
+    fn show_preview(&self, preview: &Preview) {
+        let rows = self.rows.borrow();
+        let Some(row) = rows.get(&preview.path) else {
+            return;
+        };
+        if let Some(thumbnail) = &preview.thumbnail {
+            let file = gtk4::gio::File::for_path(thumbnail);
+            row.thumbnail
+                .set_paintable(Texture::from_file(&file).ok().as_ref());
+        }
+        row.details
+            .set_label(&details_text(row.size, row.modified, preview.duration));
+    }
+
+    fn build_row(self: &Rc<Self>, item: &LibraryItem, is_active: bool) -> GtkBox {
+        let path = item.recording.path.clone();
+        let row = GtkBox::new(Orientation::Horizontal, 10);
+        row.add_css_class("library-row");
+
+        let thumbnail = Picture::new();
+        thumbnail.set_size_request(160, 90);
+        row.append(&thumbnail);
+
+        let info = GtkBox::new(Orientation::Vertical, 2);
+        info.set_hexpand(true);
+        let name = Label::new(Some(&file_name(&path)));
+        name.add_css_class("library-name");
+        name.set_xalign(0.0);
+        let details = Label::new(Some(&details_text(
+            item.recording.size,
+            item.recording.modified,
+            None,
+        )));
+        details.set_xalign(0.0);
+        let meta = Label::new(Some(&meta_text(item, is_active)));
+        meta.add_css_class("library-meta");
+        meta.set_xalign(0.0);
+        meta.set_wrap(true);
+        info.append(&name);
+        info.append(&details);
+        info.append(&meta);
+        row.append(&info);
+
+        let actions = GtkBox::new(Orientation::Vertical, 2);
+        let play = Button::with_label("Смотреть");
+        play.set_sensitive(!is_active);
+        let protect = Button::with_label(if item.recording.protected {
+            "Снять защиту"
+        } else {
+            "Защитить"
+        });
+        let rename_entry = Entry::new();
+        rename_entry.set_text(&path.file_stem().unwrap_or_default().to_string_lossy());
+        let rename = Button::with_label("Переименовать");
+        let delete = Button::with_label("Удалить");
+        delete.add_css_class("destructive-action");
+        delete.set_sensitive(!item.recording.protected);
+        for widget in [&protect, &rename, &delete] {
+            widget.set_sensitive(widget.is_sensitive() && !is_active);
+        }
+        rename_entry.set_sensitive(!is_active);
+        actions.append(&play);
+        actions.append(&protect);
+        actions.append(&rename_entry);
+        actions.append(&rename);
+        actions.append(&delete);
+        row.append(&actions);
+
+        play.connect_clicked({
+            let view = Rc::downgrade(self);
+            let path = path.clone();
+            move |_| {
+                let Some(view) = view.upgrade() else {
+                    return;
+                };
+                let on_play = view.on_play.borrow().clone();
+                if let Some(on_play) = on_play {
+                    on_play(&path);
+                }
+            }
+        });
+
+        protect.connect_clicked({
+            let view = Rc::downgrade(self);
+            let path = path.clone();
+            let protected = item.recording.protected;
+            move |_| {
+                if let Err(e) = storage::set_protected(&path, !protected) {
+                    println!("Не удалось изменить защиту {}: {}", path.display(), e);
+                }
+                if let Some(view) = view.upgrade() {
+                    view.refresh();
+                }
+            }
+        });
+
+        let do_rename = {
+            let view = Rc::downgrade(self);
+            let path = path.clone();
+            let rename_entry = rename_entry.clone();
+            move || {
+                let Some(view) = view.upgrade() else {
+                    return;
+                };
+                let name = view.namer.sanitize(&rename_entry.text());
+                if name.is_empty() {
+                    return;
+                }
+                match storage::rename_recording(&path, &name) {
+                    Ok(new_path) => println!("Запись переименована: {}", new_path.display()),
+                    Err(e) => {
+                        println!("Не удалось переименовать {}: {}", path.display(), e);
+                        view.status.set_label(&e.to_string());
+                        return;
+                    }
+                }
+                view.refresh();
+            }
+        };
+        rename.connect_clicked({
+            let do_rename = do_rename.clone();
+            move |_| do_rename()
+        });
+        rename_entry.connect_activate(move |_| do_rename());
+
+        // Удаление в два нажатия, чтобы не стереть запись случайно
+        delete.connect_clicked({
+            let view = Rc::downgrade(self);
+            let path = path.clone();
+            move |button| {
+                if button.label().is_some_and(|l| l == "Удалить") {
+                    button.set_label("Точно удалить?");
+                    return;
+                }
+                if let Err(e) = storage::remove_recording(&path) {
+                    println!("Не удалось удалить {}: {}", path.display(), e);
+                }
+                if let Some(view) = view.upgrade() {
+                    view.refresh();
+                }
+            }
+        });
+
+        self.rows.borrow_mut().insert(
+            path,
+            RowWidgets {
+                thumbnail,
+                details,
+                size: item.recording.size,
+                modified: item.recording.modified,
+            },
+        );
+        row
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn details_text(size: u64, modified: SystemTime, duration: Option<Duration>) -> String {
+    let modified = chrono::DateTime::<chrono::Local>::from(modified);
+    let duration = match duration {
+        Some(duration) => {
+            let secs = duration.as_secs();
+            format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+        }
+        None => String::from("--:--:--"),
+    };
+    format!(
+        "{}  {}  {:.1} МБ",
+        modified.format("%Y-%m-%d %H:%M"),
+        duration,
+        size as f64 / MB as f64
+    )
+}
+
+/// Сведения из файла метаданных записи
+fn meta_text(item: &LibraryItem, is_active: bool) -> String {
+    let mut parts = Vec::new();
+    if is_active {
+        parts.push(String::from("идёт запись"));
+    }
+    if item.recording.protected {
+        parts.push(String::from("защищена"));
+    }
+    if let Some(clip) = item.sidecar.as_ref().and_then(|s| s.clip.as_ref()) {
+        parts.push(format!(
+            "фрагмент {} ({} - {} с)",
+            file_name(&clip.source),
+            clip.start_ms / 1000,
+            clip.end_ms / 1000
+        ));
+    }
+    if let Some(sidecar) = &item.sidecar {
+        parts.push(format!("профиль {}", sidecar.profile_name));
+        parts.push(sidecar.source_device.clone());
+        if !sidecar.bookmarks.is_empty() {
+            parts.push(format!("меток: {}", sidecar.bookmarks.len()));
+        }
+        if !sidecar.errors.is_empty() {
+            parts.push(format!("ошибок: {}", sidecar.errors.len()));
+        }
+        if sidecar.dropped_frames > 0 {
+            parts.push(format!("потеряно кадров: {}", sidecar.dropped_frames));
+        }
+        if !sidecar.finished && !is_active {
+            parts.push(String::from("не завершена"));
+        }
+    }
+    parts.join(", ")
+}
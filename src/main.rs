@@ -1,16 +1,16 @@
 use crate::gdk::Texture;
-use chrono::prelude::*;
 use glib::timeout_add_local;
-use gstreamer::Bin;
-use gstreamer::Element;
 use gstreamer::Pipeline;
 use gstreamer::State;
 use gstreamer::prelude::*;
 use gtk4::gdk::Display;
 use gtk4::glib;
-use gtk4::{Application, ApplicationWindow, Box as GtkBox, Button, CssProvider, Picture, Spinner};
+use gtk4::{
+    Application, ApplicationWindow, Box as GtkBox, Button, CssProvider, DropDown, Entry, Label,
+    LevelBar, Overlay, Picture, Spinner,
+};
 use gtk4::{gdk, prelude::*};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fs;
 use std::path::Path;
 use std::rc::Rc;
@@ -19,116 +19,52 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use gstreamer::glib::property::PropertySet;
 
+mod app_state;
+mod audio;
+mod cli;
+mod config;
+mod discovery;
+mod dvr;
+mod export;
 mod gst_utils;
+mod library;
+mod library_view;
+mod naming;
 mod picture;
-
-use crate::gst_utils::{link_tee_branch, unlink_tee_branch};
-
-struct Config {
-    camera: CameraConfig,
-}
-
-#[derive(Clone)]
-struct CameraConfig {
-    width: i32,
-    height: i32,
-    fps: i32,
-    path: String,
-}
-
-struct AppState {
-    pipeline: Pipeline,
-    tee: Element,
-    recording_branch: Option<Bin>,
-    is_recording: bool,
-}
-
-impl AppState {
-    fn new(pipeline: Pipeline) -> Self {
-        let tee = pipeline
-            .by_name("t")
-            .expect("Не удалось найти элемент tee в pipeline");
-
-        Self {
-            pipeline,
-            tee,
-            recording_branch: None,
-            is_recording: false,
-        }
-    }
-
-    fn start_recording(&mut self, file_path: &str) {
-        if self.is_recording {
-            return;
-        }
-
-        println!("Начинаем запись в файл: {}", file_path);
-
-        // Обновленная строка для branch записи с улучшенными параметрами кодирования
-        let branch_str = format!(
-            "queue ! videoconvert ! x264enc tune=zerolatency speed-preset=superfast \
-            key-int-max=30 ! video/x-h264,profile=main ! mp4mux streamable=true \
-            fragment-duration=1 ! filesink location={} sync=false",
-            file_path
-        );
-
-        println!("Создаем branch с настройками: {}", branch_str);
-
-        match gstreamer::parse::bin_from_description(&branch_str, true) {
-            Ok(branch) => match link_tee_branch(&self.pipeline, &self.tee, &branch) {
-                Ok(_) => {
-                    println!("Branch успешно подключен");
-                    self.recording_branch = Some(branch);
-                    self.is_recording = true;
-                }
-                Err(e) => println!("Ошибка при подключении branch: {:?}", e),
-            },
-            Err(e) => println!("Ошибка создания branch: {:?}", e),
-        }
-    }
-
-    fn stop_recording(&mut self) {
-        if !self.is_recording {
-            return;
-        }
-
-        if let Some(branch) = self.recording_branch.take() {
-            println!("Останавливаем запись...");
-
-            // Сначала отправляем EOS для branch записи
-            if let Some(sink_pad) = branch.static_pad("sink") {
-                sink_pad.send_event(gstreamer::event::Eos::new());
-            }
-
-            // Увеличиваем время ожидания для корректного завершения записи
-            std::thread::sleep(std::time::Duration::from_millis(500));
-
-            // Переводим branch в состояние NULL
-            let _ = branch.set_state(State::Null);
-
-            // Отключаем branch записи
-            unlink_tee_branch(
-                &self.pipeline,
-                &self.tee,
-                &branch,
-                Box::new(|| {
-                    println!("Branch записи отключен");
-                }),
-            );
-
-            // Перезапускаем pipeline
-            let _ = self.pipeline.set_state(State::Null);
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            let _ = self.pipeline.set_state(State::Playing);
-
-            // Ждем, пока pipeline действительно перейдет в состояние PLAYING
-            let _ = self.pipeline.state(gstreamer::ClockTime::from_seconds(1));
-
-            println!("Pipeline перезапущен");
-        }
-
-        self.is_recording = false;
-    }
+mod player;
+mod player_view;
+mod profile;
+mod reconnect;
+mod recover;
+mod replay;
+mod replay_view;
+mod rtp_stats;
+mod session;
+mod sidecar;
+mod snapshot;
+mod storage;
+mod video_source;
+
+use crate::app_state::{AppState, RecordingEvent};
+use crate::audio::AudioInput;
+use crate::cli::{Cli, Command};
+use crate::config::Config;
+use crate::library_view::LibraryView;
+use crate::naming::FileNamer;
+use crate::player_view::PlayerView;
+use crate::reconnect::{SignalState, SourceManager};
+use crate::replay::ReplayBuffer;
+use crate::replay_view::ReplayView;
+use crate::storage::StorageManager;
+use crate::video_source::VideoSource;
+use clap::Parser;
+
+/// Что показано в окне видео
+#[derive(Clone, Copy, PartialEq)]
+enum Screen {
+    Live,
+    Library,
+    Playback,
 }
 
 fn load_css() {
@@ -142,7 +78,21 @@ fn load_css() {
     );
 }
 
-fn handle_pipeline_messages(bus: &gstreamer::Bus, pipeline: &gstreamer::Pipeline) -> bool {
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn handle_pipeline_messages(
+    bus: &gstreamer::Bus,
+    pipeline: &gstreamer::Pipeline,
+    sources: &SourceManager,
+    audio_input: Option<&AudioInput>,
+    app_state: &RefCell<AppState>,
+    audio_level: &LevelBar,
+) -> bool {
     while let Some(msg) = bus.pop() {
         match msg.view() {
             gstreamer::MessageView::Error(err) => {
@@ -153,16 +103,41 @@ fn handle_pipeline_messages(bus: &gstreamer::Bus, pipeline: &gstreamer::Pipeline
                     err.debug()
                 );
 
+                app_state.borrow_mut().log_error(
+                    &err.src()
+                        .map(|s| s.path_string().to_string())
+                        .unwrap_or_default(),
+                    &err.error().to_string(),
+                );
+
                 // Проверяем, от какого элемента пришла ошибка
                 if let Some(src) = err.src() {
-                    // Игнорируем ошибки от элементов записи
-                    if src.name().as_str().starts_with("filesink")
-                        || src.name().as_str().starts_with("qtmux")
-                        || src.name().as_str().starts_with("x264enc")
-                        || src.name().as_str().starts_with("queue")
+                    // Ошибки от уже удалённого источника приходят с опозданием
+                    if !src.has_as_ancestor(pipeline) {
+                        println!("Игнорируем ошибку от удалённого элемента: {}", src.name());
+                        continue;
+                    }
+
+                    // Источник переподключается сам, остальной pipeline не трогаем
+                    if sources.owns(src) {
+                        sources.handle_source_error();
+                        continue;
+                    }
+
+                    // Без микрофона видео и запись продолжаются с тишиной
+                    if let Some(audio_input) = audio_input
+                        && audio_input.owns(src)
                     {
-                        println!("Игнорируем ошибку от элемента: {}", src.name());
-                        return true;
+                        audio_input.handle_source_error();
+                        continue;
+                    }
+
+                    // Ошибка записи останавливает только ветку записи
+                    if app_state.borrow().owns(src) {
+                        app_state
+                            .borrow_mut()
+                            .handle_branch_error(src, &err.error().to_string());
+                        continue;
                     }
                 }
 
@@ -172,6 +147,25 @@ fn handle_pipeline_messages(bus: &gstreamer::Bus, pipeline: &gstreamer::Pipeline
                 let _ = pipeline.set_state(State::Playing);
                 return true;
             }
+            gstreamer::MessageView::Progress(progress) => {
+                // rtspsrc сообщает этапы подключения: open, request, ...
+                let (kind, code, text) = progress.get();
+                println!("Поток: {:?} {} {}", kind, code, text);
+                if progress.src().is_some_and(|src| sources.owns(src)) {
+                    sources.set_stream_status(format!("RTSP: {}", text));
+                }
+            }
+            gstreamer::MessageView::Warning(warning) => {
+                println!(
+                    "Warning from {:?}: {} ({:?})",
+                    warning.src().map(|s| s.name()),
+                    warning.error(),
+                    warning.debug()
+                );
+                if warning.src().is_some_and(|src| sources.owns(src)) {
+                    sources.set_stream_status(format!("RTSP: {}", warning.error()));
+                }
+            }
             gstreamer::MessageView::StateChanged(state_changed) => {
                 // Логируем изменения состояния для отладки
                 if let Some(element) = state_changed.src() {
@@ -183,6 +177,12 @@ fn handle_pipeline_messages(bus: &gstreamer::Bus, pipeline: &gstreamer::Pipeline
                     );
                 }
             }
+            gstreamer::MessageView::Element(_) => {
+                match msg.structure().and_then(audio::peak_level) {
+                    Some(level) => audio_level.set_value(level),
+                    None => app_state.borrow_mut().handle_element_message(&msg),
+                }
+            }
             gstreamer::MessageView::Eos(_) => {
                 // Игнорируем EOS от branch записи
                 println!("Получен EOS, игнорируем");
@@ -195,36 +195,94 @@ fn handle_pipeline_messages(bus: &gstreamer::Bus, pipeline: &gstreamer::Pipeline
 }
 
 fn main() {
-    let app = Application::new(Some("com.example.MyGTKApp"), Default::default());
-    app.connect_startup(|_| load_css());
+    let cli = Cli::parse();
 
-    let config = Config {
-        camera: CameraConfig {
-            width: 720,
-            height: 480,
-            fps: 25,
-            path: String::from("src/media/"),
-        },
+    let mut config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Ошибка конфигурации: {}", e);
+            std::process::exit(2);
+        }
     };
 
-    let camera_config = config.camera.clone();
+    if cli.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    let app = Application::new(Some("com.example.MyGTKApp"), Default::default());
+    app.connect_startup(|_| load_css());
 
     gstreamer::init().expect("Не удалось инициализировать GStreamer");
     gstgtk4::plugin_register_static().expect("Failed to register gstgtk4 plugin");
 
+    if let Some(Command::Recover { dir, dry_run }) = &cli.command {
+        let dir = dir
+            .clone()
+            .unwrap_or_else(|| config.camera.path.clone().into());
+        if let Err(e) = recover::run(&dir, *dry_run) {
+            eprintln!("Ошибка восстановления: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if cli.list_devices {
+        if let Err(e) = discovery::print_devices() {
+            eprintln!("Ошибка опроса устройств: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    discovery::fit_camera_config(&mut config.camera);
+    let camera_config = config.camera.clone();
+    let recording_config = config.recording.clone();
+    let storage_config = config.storage.clone();
+    let audio_config = config.audio.clone();
+    let naming_config = config.naming.clone();
+
     let media_path = Path::new(&config.camera.path);
     if !media_path.exists() {
         fs::create_dir_all(media_path).expect("Failed to create media directory");
     }
 
-    let pipeline_str = format!(
-        "v4l2src device=/dev/video0 ! image/jpeg,width={},height={},framerate={}/1 ! 
-        jpegdec ! videoconvert ! tee name=t allow-not-linked=true ! 
+    println!("Источник видео: {}", config.camera.source.name());
+
+    // Источник подключается к input-selector отдельным bin, см. SourceManager.
+    // Первый вход селектора - заставка, на которую он переключается без сигнала.
+    // После селектора caps фиксируются, чтобы смена входа не меняла формат в tee.
+    // В jt приходят JPEG кадры MJPEG камеры до декодирования.
+    let framerate = config.camera.framerate();
+    let mut pipeline_str = format!(
+        "input-selector name=sel sync-streams=false ! videoconvert ! videoscale ! 
+        videorate name=rate ! video/x-raw,format=I420,width={w},height={h},framerate={fps_n}/{fps_d} ! 
+        tee name=t allow-not-linked=true ! 
         queue max-size-buffers=2 leaky=downstream ! videoconvert ! 
-        gtk4paintablesink name=sink1 sync=false",
-        config.camera.width, config.camera.height, config.camera.fps
+        gtk4paintablesink name=sink1 sync=false 
+        tee name=jt allow-not-linked=true 
+        videotestsrc is-live=true pattern=black ! 
+        video/x-raw,width={w},height={h},framerate={fps_n}/{fps_d} ! 
+        clockoverlay text=\"NO SIGNAL\" time-format=\"%Y-%m-%d %H:%M:%S\" 
+        halignment=center valignment=center font-desc=\"Sans 24\" ! 
+        videoconvert ! sel.",
+        w = config.camera.width,
+        h = config.camera.height,
+        fps_n = framerate.numer(),
+        fps_d = framerate.denom()
     );
 
+    // Звук идёт в том же pipeline, чтобы у видео и звука были общие часы.
+    // Источник звука подключается отдельным bin, см. AudioInput.
+    if config.audio.enabled {
+        pipeline_str.push(' ');
+        pipeline_str.push_str(&audio::pipeline_segment());
+    }
+    if config.camera.replay_seconds > 0 {
+        pipeline_str.push(' ');
+        pipeline_str.push_str(&replay::pipeline_segment());
+    }
+
     let pipeline = gstreamer::parse::launch(&pipeline_str)
         .expect("Can not create GStreamer pipeline")
         .dynamic_cast::<Pipeline>()
@@ -232,6 +290,16 @@ fn main() {
 
     let pipeline_weak = pipeline.downgrade();
 
+    let audio_input = config
+        .audio
+        .enabled
+        .then(|| AudioInput::new(&pipeline, &config.audio.source));
+
+    let replay_buffer = match config.camera.replay_seconds {
+        0 => None,
+        seconds => ReplayBuffer::new(&pipeline, seconds),
+    };
+
     app.connect_activate(move |app| {
         let window = ApplicationWindow::new(app);
         window.set_title(Some("My GTK App"));
@@ -297,17 +365,133 @@ fn main() {
 
         vbox1.append(&button1);
         vbox1.append(&button2);
+
+        // Снимок кадра и миниатюра последнего снимка, которая скрывается сама
+        let snapshot_button = Button::with_label("Снимок");
+        snapshot_button.add_css_class("custom-button");
+        vbox1.append(&snapshot_button);
+
+        let snapshot_toast = GtkBox::new(gtk4::Orientation::Vertical, 2);
+        snapshot_toast.add_css_class("snapshot-toast");
+        snapshot_toast.set_visible(false);
+        let snapshot_thumbnail = Picture::new();
+        snapshot_thumbnail.set_size_request(160, 90);
+        let snapshot_label = Label::new(None);
+        snapshot_label.set_wrap(true);
+        snapshot_toast.append(&snapshot_thumbnail);
+        snapshot_toast.append(&snapshot_label);
+        vbox1.append(&snapshot_toast);
+
+        // Библиотека записей открывается на месте картинки с камеры
+        let library_button = Button::with_label("Записи");
+        library_button.add_css_class("custom-button");
+        vbox1.append(&library_button);
+
+        // Повтор последних секунд поверх картинки, эфир и запись идут дальше
+        let replay_button = Button::with_label("Повтор");
+        replay_button.add_css_class("custom-button");
+        replay_button.set_visible(replay_buffer.is_some());
+        vbox1.append(&replay_button);
+
+        // Статистика приёма и состояние потока для сетевых источников
+        let source_info_label = Label::new(None);
+        source_info_label.add_css_class("stats-label");
+        source_info_label.set_visible(matches!(
+            camera_config.source,
+            VideoSource::Rtp { .. } | VideoSource::Rtsp { .. }
+        ));
+        vbox1.append(&source_info_label);
+
+        // Уровень звука с микрофона
+        let audio_level = LevelBar::for_interval(0.0, 1.0);
+        audio_level.add_css_class("audio-level");
+        audio_level.set_visible(audio_config.enabled);
+        vbox1.append(&audio_level);
         display_window.append(&picture);
         vbox3.append(&button3);
+
+        // Выбор профиля записи, недоступен во время записи
+        let profile_dropdown = DropDown::from_strings(&[]);
+        profile_dropdown.add_css_class("profile-dropdown");
+        vbox3.append(&profile_dropdown);
+
         vbox3.append(&button_rec);
 
+        // Метка момента записи с необязательной подписью
+        let marker_entry = Entry::new();
+        marker_entry.set_placeholder_text(Some("Подпись метки"));
+        marker_entry.add_css_class("marker-entry");
+        let marker_button = Button::with_label("Метка");
+        marker_button.add_css_class("custom-button");
+        marker_button.set_sensitive(false);
+        vbox3.append(&marker_entry);
+        vbox3.append(&marker_button);
+
+        // Причина неудачной записи и предупреждения о месте на диске
+        let warning_label = Label::new(None);
+        warning_label.add_css_class("warning-label");
+        warning_label.set_wrap(true);
+        warning_label.set_visible(false);
+        vbox3.append(&warning_label);
+
+        // Файл текущей записи, затем итог сохранения
+        let status_label = Label::new(None);
+        status_label.add_css_class("recording-status");
+        status_label.set_wrap(true);
+        vbox3.append(&status_label);
+
+        let overlay = Overlay::new();
+        overlay.set_child(Some(&display_window));
+        let replay_view = replay_buffer.clone().map(ReplayView::new);
+        if let Some(replay_view) = &replay_view {
+            overlay.add_overlay(replay_view.widget());
+            replay_button.connect_clicked({
+                let replay_view = replay_view.clone();
+                move |_| replay_view.start()
+            });
+        }
+
         hbox.append(&vbox1);
-        hbox.append(&display_window);
+        hbox.append(&overlay);
         hbox.append(&vbox3);
 
         window.set_child(Some(&hbox));
         window.show();
 
+        // Заставка "NO SIGNAL" идёт в самом видео, окно только подсвечивается
+        let selector = pipeline
+            .by_name("sel")
+            .expect("Не удалось найти элемент input-selector в pipeline");
+        let jpeg_tee = pipeline
+            .by_name("jt")
+            .expect("Не удалось найти элемент jpeg tee в pipeline");
+        let sources = SourceManager::new(&pipeline, &selector, &jpeg_tee, &camera_config);
+        sources.connect_state_changed({
+            let display_window = display_window.clone();
+            move |state| {
+                if state == SignalState::NoSignal {
+                    display_window.add_css_class("no-signal");
+                } else {
+                    display_window.remove_css_class("no-signal");
+                }
+            }
+        });
+        display_window.add_css_class("no-signal");
+        sources.start();
+
+        if source_info_label.is_visible() {
+            let sources = sources.clone();
+            timeout_add_local(Duration::from_secs(1), move || {
+                let text = match (sources.rtp_stats(), sources.stream_status()) {
+                    (Some(stats), _) => stats.summary(),
+                    (None, Some(status)) => status,
+                    (None, None) => String::from("Нет данных"),
+                };
+                source_info_label.set_label(&text);
+                glib::ControlFlow::Continue
+            });
+        }
+
         pipeline
             .set_state(State::Playing)
             .expect("Unable to set the pipeline to the `Playing` state");
@@ -455,39 +639,342 @@ fn main() {
             }
         });
 
-        let app_state = Rc::new(RefCell::new(AppState::new(pipeline.clone())));
+        let namer = Rc::new(
+            FileNamer::new(Path::new(&camera_config.path), &naming_config)
+                .expect("Неверный шаблон имени записи"),
+        );
 
+        // Кадр берётся с tee до всех веток, запись при этом не прерывается.
+        // Имя снимка строится по тому же шаблону, что и имена записей.
+        snapshot_button.connect_clicked({
+            let tee = pipeline
+                .by_name("t")
+                .expect("Не удалось найти элемент tee в pipeline");
+            let namer = namer.clone();
+            let source_name = camera_config.source.name();
+            let format = camera_config.snapshot_format;
+            let hide_id = Rc::new(RefCell::new(None::<glib::SourceId>));
+            move |button| {
+                let file_path = match namer.recording_path(
+                    source_name,
+                    snapshot::PROFILE_NAME,
+                    format.extension(),
+                ) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        println!("Ошибка снимка: {}", e);
+                        return;
+                    }
+                };
+
+                button.set_sensitive(false);
+                let tee = tee.clone();
+                let button = button.clone();
+                let toast = snapshot_toast.clone();
+                let thumbnail = snapshot_thumbnail.clone();
+                let label = snapshot_label.clone();
+                let hide_id = hide_id.clone();
+                glib::spawn_future_local(async move {
+                    let result = snapshot::capture(&tee, file_path, format).await;
+                    button.set_sensitive(true);
+
+                    match result {
+                        Ok(path) => {
+                            let file = gtk4::gio::File::for_path(&path);
+                            thumbnail.set_paintable(Texture::from_file(&file).ok().as_ref());
+                            label
+                                .set_label(&path.file_name().unwrap_or_default().to_string_lossy());
+                        }
+                        Err(e) => {
+                            println!("Ошибка снимка: {}", e);
+                            thumbnail.set_paintable(None::<&Texture>);
+                            label.set_label(&e);
+                        }
+                    }
+
+                    toast.set_visible(true);
+                    if let Some(id) = hide_id.take() {
+                        id.remove();
+                    }
+                    let source_id = glib::timeout_add_local_once(Duration::from_secs(3), {
+                        let hide_id = hide_id.clone();
+                        move || {
+                            toast.set_visible(false);
+                            hide_id.take();
+                        }
+                    });
+                    hide_id.replace(Some(source_id));
+                });
+            }
+        });
+
+        let app_state = Rc::new(RefCell::new(AppState::new(
+            pipeline.clone(),
+            &camera_config,
+            &recording_config,
+            &audio_config,
+            StorageManager::new(Path::new(&camera_config.path), &storage_config),
+        )));
+        let recording_events = app_state.borrow_mut().subscribe();
+
+        let profile_names = app_state.borrow().profile_names();
+        let profile_list =
+            gtk4::StringList::new(&profile_names.iter().map(String::as_str).collect::<Vec<_>>());
+        profile_dropdown.set_model(Some(&profile_list));
+        if let Some(index) = profile_names
+            .iter()
+            .position(|name| name == app_state.borrow().profile_name())
+        {
+            profile_dropdown.set_selected(index as u32);
+        }
+        profile_dropdown.connect_selected_notify({
+            let app_state = app_state.clone();
+            move |dropdown| {
+                let Some(name) = profile_names.get(dropdown.selected() as usize) else {
+                    return;
+                };
+                if let Err(e) = app_state.borrow_mut().set_profile(name) {
+                    println!("Ошибка выбора профиля: {}", e);
+                }
+            }
+        });
+
+        let library = LibraryView::new(
+            StorageManager::new(Path::new(&camera_config.path), &storage_config),
+            namer.clone(),
+            {
+                let app_state = app_state.clone();
+                move || app_state.borrow().paths_in_use()
+            },
+        );
+        let player_view = PlayerView::new(camera_config.clone(), {
+            let app_state = app_state.clone();
+            move || {
+                let state = app_state.borrow();
+                (state.profile_name().to_string(), state.profile().clone())
+            }
+        });
+
+        // Камера, библиотека или просмотр записи на месте картинки с камеры.
+        // Живой pipeline и запись при этом продолжают работать.
+        let screen = Rc::new(Cell::new(Screen::Live));
+        let show_screen = Rc::new({
+            let display_window = display_window.clone();
+            let picture = picture.clone();
+            let library = library.clone();
+            let player_view = player_view.clone();
+            let library_button = library_button.clone();
+            let screen = screen.clone();
+            move |next: Screen| {
+                if let Some(child) = display_window.first_child() {
+                    display_window.remove(&child);
+                }
+                if screen.get() == Screen::Playback && next != Screen::Playback {
+                    player_view.close();
+                }
+                match next {
+                    Screen::Live => display_window.append(&*picture.borrow()),
+                    Screen::Library => {
+                        display_window.append(library.widget());
+                        library.refresh();
+                    }
+                    Screen::Playback => display_window.append(player_view.widget()),
+                }
+                library_button.set_label(if next == Screen::Live {
+                    "Записи"
+                } else {
+                    "Камера"
+                });
+                screen.set(next);
+            }
+        });
+
+        library_button.connect_clicked({
+            let show_screen = show_screen.clone();
+            move |_| {
+                show_screen(if screen.get() == Screen::Live {
+                    Screen::Library
+                } else {
+                    Screen::Live
+                })
+            }
+        });
+        library.connect_play({
+            let show_screen = show_screen.clone();
+            let player_view = player_view.clone();
+            move |path| match player_view.open(path) {
+                Ok(()) => show_screen(Screen::Playback),
+                Err(e) => println!("Не удалось открыть {}: {}", path.display(), e),
+            }
+        });
+        player_view.connect_live(move || show_screen(Screen::Live));
+        // Новый фрагмент сразу появляется в списке записей
+        player_view.connect_exported({
+            let library = library.clone();
+            move |_| library.refresh()
+        });
+
+        // Кнопка только запускает действие, её вид меняется по событиям записи
         button_rec.connect_clicked({
             let app_state = app_state.clone();
-            let camera_path = camera_config.path.clone();
-            move |button| {
+            let source_name = camera_config.source.name();
+            move |_| {
                 let mut state = app_state.borrow_mut();
-                if !state.is_recording {
-                    let now = Utc::now();
-                    let file_path =
-                        format!("{}{}.mp4", camera_path, now.format("%Y-%m-%d|%H:%M:%S"));
-                    state.start_recording(&file_path);
-                    button.add_css_class("recording");
-                    button.set_label("Стоп запись");
+                let result = if !state.is_recording() {
+                    match namer.recording_path(
+                        source_name,
+                        state.profile_name(),
+                        state.profile().container.extension(),
+                    ) {
+                        Ok(file_path) => state.start_recording(&file_path.to_string_lossy()),
+                        Err(e) => Err(e.into()),
+                    }
                 } else {
-                    state.stop_recording();
-                    button.remove_css_class("recording");
-                    button.set_label("Запись видео");
+                    state.stop_recording()
+                };
+
+                if let Err(e) = result {
+                    println!("Ошибка записи: {}", e);
+                }
+            }
+        });
+
+        let add_marker = {
+            let app_state = app_state.clone();
+            let marker_entry = marker_entry.clone();
+            move || {
+                let text = marker_entry.text();
+                let label = Some(text.trim()).filter(|t| !t.is_empty());
+                match app_state.borrow_mut().add_marker(label) {
+                    Ok(offset) => {
+                        let secs = offset.as_secs();
+                        marker_entry.set_text("");
+                        marker_entry.set_placeholder_text(Some(&format!(
+                            "Метка {:02}:{:02}:{:02} добавлена",
+                            secs / 3600,
+                            secs / 60 % 60,
+                            secs % 60
+                        )));
+                    }
+                    Err(e) => println!("Ошибка метки: {}", e),
+                }
+            }
+        };
+        marker_button.connect_clicked({
+            let add_marker = add_marker.clone();
+            move |_| add_marker()
+        });
+        marker_entry.connect_activate(move |_| add_marker());
+
+        // Квота и свободное место проверяются реже, чем приходят события
+        timeout_add_local(Duration::from_secs(5), {
+            let app_state = app_state.clone();
+            move || {
+                app_state.borrow_mut().check_storage();
+                app_state.borrow_mut().update_sidecar();
+                glib::ControlFlow::Continue
+            }
+        });
+
+        let button_rec_weak = button_rec.downgrade();
+        let profile_dropdown_weak = profile_dropdown.downgrade();
+        let warning_label_weak = warning_label.downgrade();
+        let status_label_weak = status_label.downgrade();
+        let marker_button_weak = marker_button.downgrade();
+        timeout_add_local(Duration::from_millis(100), move || {
+            let (
+                Some(button),
+                Some(profile_dropdown),
+                Some(warning_label),
+                Some(status_label),
+                Some(marker_button),
+            ) = (
+                button_rec_weak.upgrade(),
+                profile_dropdown_weak.upgrade(),
+                warning_label_weak.upgrade(),
+                status_label_weak.upgrade(),
+                marker_button_weak.upgrade(),
+            )
+            else {
+                return glib::ControlFlow::Break;
+            };
+
+            while let Ok(event) = recording_events.try_recv() {
+                match event {
+                    RecordingEvent::Started { path } => {
+                        status_label.set_label(&format!("Запись: {}", file_name(&path)));
+                        warning_label.set_visible(false);
+                        button.add_css_class("recording");
+                        button.set_label("Стоп запись");
+                        profile_dropdown.set_sensitive(false);
+                        marker_button.set_sensitive(true);
+                    }
+                    RecordingEvent::Finalizing => {
+                        button.remove_css_class("recording");
+                        button.set_label("Сохранение...");
+                        button.set_sensitive(false);
+                        marker_button.set_sensitive(false);
+                    }
+                    RecordingEvent::Finished { .. } | RecordingEvent::Failed { .. } => {
+                        match &event {
+                            RecordingEvent::Finished {
+                                path,
+                                duration,
+                                size,
+                            } => {
+                                let secs = duration.as_secs();
+                                status_label.set_label(&format!(
+                                    "Сохранено: {}, {:02}:{:02}:{:02}, {:.1} МБ",
+                                    file_name(path),
+                                    secs / 3600,
+                                    secs / 60 % 60,
+                                    secs % 60,
+                                    *size as f64 / (1024.0 * 1024.0)
+                                ));
+                            }
+                            RecordingEvent::Failed { reason } => {
+                                status_label.set_label("");
+                                warning_label.set_label(reason);
+                                warning_label.set_visible(true);
+                            }
+                            _ => {}
+                        }
+                        button.remove_css_class("recording");
+                        button.set_label("Запись видео");
+                        button.set_sensitive(true);
+                        profile_dropdown.set_sensitive(true);
+                        marker_button.set_sensitive(false);
+                    }
+                    RecordingEvent::Warning { message } => {
+                        warning_label.set_label(&message);
+                        warning_label.set_visible(true);
+                    }
                 }
             }
+            glib::ControlFlow::Continue
         });
 
         let bus = pipeline.bus().expect("Не удалось получить шину pipeline");
         let app_weak = app.downgrade();
 
         let pipeline_weak = pipeline_weak.clone();
+        let audio_input = audio_input.clone();
         timeout_add_local(Duration::from_millis(100), move || {
             let pipeline = match pipeline_weak.upgrade() {
                 Some(p) => p,
                 None => return glib::ControlFlow::Break,
             };
 
-            if !handle_pipeline_messages(&bus, &pipeline) {
+            sources.poll();
+
+            if !handle_pipeline_messages(
+                &bus,
+                &pipeline,
+                &sources,
+                audio_input.as_ref(),
+                &app_state,
+                &audio_level,
+            ) {
                 if let Some(app) = app_weak.upgrade() {
                     app.quit();
                 }
@@ -497,5 +984,7 @@ fn main() {
         });
     });
 
-    app.run();
+    // Аргументы уже разобраны clap, GTK получает только имя программы
+    let program = std::env::args().next().unwrap_or_default();
+    app.run_with_args(&[program]);
 }
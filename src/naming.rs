@@ -0,0 +1,425 @@
+use crate::config::NamingConfig;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Символы, которые нельзя использовать в именах на FAT/exFAT
+const FAT_FORBIDDEN: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Имена устройств, которые FAT не даёт использовать как имя файла
+const FAT_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Предел длины имени в байтах, с запасом под расширение и файлы рядом с записью
+const MAX_NAME_LEN: usize = 200;
+
+/// Разделители, которые пустая подстановка убирает вместе с собой
+const SEPARATORS: [char; 4] = ['_', '-', '.', ' '];
+
+/// Сколько номеров перебирать, пока не найдётся свободное имя
+const MAX_ATTEMPTS: u32 = 10_000;
+
+/// Часы для времени в имени файла
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Clock {
+    Local,
+    Utc,
+}
+
+/// Файловая система, на которую попадут записи
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetFs {
+    /// FAT и exFAT: без `<>:"/\|?*`, без точки и пробела в конце
+    Fat,
+    Posix,
+}
+
+/// Часть шаблона имени
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Date(String),
+    Time(String),
+    Callsign,
+    Source,
+    Profile,
+    Seq(usize),
+}
+
+/// Разбирает шаблон вида `{date}_{time:%H%M}_{callsign}_{seq:4}`.
+/// `/` в тексте шаблона разделяет каталоги.
+fn parse_template(template: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("незакрытая скобка в шаблоне '{}'", template))?;
+        let field = &rest[start + 1..start + end];
+        let (name, arg) = match field.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (field, None),
+        };
+
+        let part = match (name, arg) {
+            ("date", arg) => Part::Date(strftime(arg.unwrap_or("%Y-%m-%d"))?),
+            ("time", arg) => Part::Time(strftime(arg.unwrap_or("%H-%M-%S"))?),
+            ("callsign", None) => Part::Callsign,
+            ("source", None) => Part::Source,
+            ("profile", None) => Part::Profile,
+            ("seq", None) => Part::Seq(3),
+            ("seq", Some(width)) => Part::Seq(
+                width
+                    .parse()
+                    .ok()
+                    .filter(|w| (1..=9).contains(w))
+                    .ok_or_else(|| format!("ширина номера должна быть от 1 до 9: '{}'", width))?,
+            ),
+            _ => return Err(format!("неизвестная подстановка '{{{}}}'", field)),
+        };
+        parts.push(part);
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+fn strftime(format: &str) -> Result<String, String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("неверный формат времени '{}'", format));
+    }
+    Ok(format.to_string())
+}
+
+/// Проверка шаблона при загрузке конфигурации
+pub fn validate_template(template: &str) -> Result<(), String> {
+    let parts = parse_template(template)?;
+    if parts.is_empty() {
+        return Err(String::from("пустой шаблон имени"));
+    }
+    Ok(())
+}
+
+/// Подбирает имена новых записей по шаблону из `[naming]`
+pub struct FileNamer {
+    dir: PathBuf,
+    config: NamingConfig,
+    parts: Vec<Part>,
+}
+
+impl FileNamer {
+    pub fn new(dir: &Path, config: &NamingConfig) -> Result<Self, String> {
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            config: config.clone(),
+            parts: parse_template(&config.template)?,
+        })
+    }
+
+    /// Свободный путь для новой записи. Каталоги по пути создаются.
+    /// Если шаблон без `{seq}`, при совпадении к имени добавляется `_2`, `_3`...
+    /// Занятым считается и каталог сессии с тем же именем без расширения.
+    pub fn recording_path(
+        &self,
+        source: &str,
+        profile: &str,
+        extension: &str,
+    ) -> io::Result<PathBuf> {
+        let now = self.now();
+        let mut dir = self.dir.clone();
+        if self.config.subdir_per_day {
+            dir.push(now.format("%Y-%m-%d").to_string());
+        }
+
+        let has_seq = self.parts.iter().any(|p| matches!(p, Part::Seq(_)));
+        for attempt in 1..=MAX_ATTEMPTS {
+            let mut name = self.render(&now, source, profile, attempt);
+            if !has_seq && attempt > 1 {
+                name = format!("{}_{}", name, attempt);
+            }
+
+            let mut components: Vec<String> = name
+                .split('/')
+                .map(|c| self.sanitize(c))
+                .filter(|c| !c.is_empty())
+                .collect();
+            let file_stem = components
+                .pop()
+                .unwrap_or_else(|| String::from("recording"));
+
+            let mut path = dir.clone();
+            path.extend(components);
+            let session_dir = path.join(&file_stem);
+            path.push(format!("{}.{}", file_stem, extension));
+
+            if !path.exists() && !session_dir.exists() {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                return Ok(path);
+            }
+        }
+
+        Err(io::Error::other(format!(
+            "не удалось подобрать свободное имя в {}",
+            dir.display()
+        )))
+    }
+
+    fn now(&self) -> DateTime<FixedOffset> {
+        match self.config.clock {
+            Clock::Local => Local::now().into(),
+            Clock::Utc => Utc::now().into(),
+        }
+    }
+
+    /// Собирает имя по шаблону. Пустая подстановка забирает с собой
+    /// разделитель после неё, чтобы `{date}_{callsign}_{seq}` без позывного
+    /// не давал `__`. Разделители в тексте шаблона остаются как есть.
+    fn render(&self, now: &DateTime<FixedOffset>, source: &str, profile: &str, seq: u32) -> String {
+        // Подставленные значения не должны создавать каталоги
+        let value = |text: &str| text.replace(['/', '\\'], "_");
+
+        let mut name = String::new();
+        let mut after_empty = false;
+        for part in &self.parts {
+            let text = match part {
+                Part::Text(text) => {
+                    let text = match after_empty {
+                        true => text.strip_prefix(SEPARATORS).unwrap_or(text),
+                        false => text,
+                    };
+                    name.push_str(text);
+                    after_empty = false;
+                    continue;
+                }
+                Part::Date(format) | Part::Time(format) => value(&now.format(format).to_string()),
+                Part::Callsign => value(&self.config.callsign),
+                Part::Source => value(source),
+                Part::Profile => value(profile),
+                Part::Seq(width) => format!("{:0width$}", seq, width = width),
+            };
+            after_empty = text.is_empty()
+                && (name.is_empty() || name.ends_with('/') || name.ends_with(SEPARATORS));
+            name.push_str(&text);
+        }
+        name
+    }
+
+    /// Приводит одну часть пути к виду, допустимому на целевой файловой
+    /// системе
+    pub fn sanitize(&self, component: &str) -> String {
+        let fat = self.config.filesystem == TargetFs::Fat;
+        let name: String = component
+            .chars()
+            .map(|c| {
+                if c == '\0' || (fat && (c.is_control() || FAT_FORBIDDEN.contains(&c))) {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect();
+
+        let mut name = name
+            .trim_matches(|c: char| c == '_' || c == '.' || c.is_whitespace())
+            .to_string();
+
+        if fat {
+            let stem = name.split('.').next().unwrap_or_default().to_uppercase();
+            if FAT_RESERVED.contains(&stem.as_str()) {
+                name.insert(0, '_');
+            }
+        }
+
+        if name.len() > MAX_NAME_LEN {
+            let mut end = MAX_NAME_LEN;
+            while !name.is_char_boundary(end) {
+                end -= 1;
+            }
+            name.truncate(end);
+            name = name.trim_end_matches(['.', ' ']).to_string();
+        }
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn namer(template: &str, callsign: &str, filesystem: TargetFs) -> FileNamer {
+        let config = NamingConfig {
+            template: template.to_string(),
+            callsign: callsign.to_string(),
+            filesystem,
+            ..NamingConfig::default()
+        };
+        FileNamer::new(Path::new("."), &config).unwrap()
+    }
+
+    fn render(namer: &FileNamer) -> String {
+        let now = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2024, 5, 6, 7, 8, 9)
+            .unwrap();
+        namer.render(&now, "v4l2", "mkv", 7)
+    }
+
+    #[test]
+    fn parse_template_reads_fields() {
+        let parts = parse_template("rec/{date:%Y}_{time}_{callsign}_{seq:4}.x").unwrap();
+        assert!(matches!(&parts[0], Part::Text(text) if text == "rec/"));
+        assert!(matches!(&parts[1], Part::Date(format) if format == "%Y"));
+        assert!(matches!(&parts[3], Part::Time(format) if format == "%H-%M-%S"));
+        assert!(matches!(parts[5], Part::Callsign));
+        assert!(matches!(parts[7], Part::Seq(4)));
+        assert!(matches!(&parts[8], Part::Text(text) if text == ".x"));
+        assert!(matches!(parse_template("{seq}").unwrap()[0], Part::Seq(3)));
+    }
+
+    #[test]
+    fn validate_template_rejects_bad_templates() {
+        assert!(validate_template("{date}_{source}_{profile}").is_ok());
+        assert!(validate_template("").is_err());
+        assert!(validate_template("{date").is_err());
+        assert!(validate_template("{unknown}").is_err());
+        assert!(validate_template("{callsign:x}").is_err());
+        assert!(validate_template("{seq:0}").is_err());
+        assert!(validate_template("{seq:10}").is_err());
+        assert!(validate_template("{time:%Q}").is_err());
+    }
+
+    #[test]
+    fn empty_substitution_takes_its_separator() {
+        let with_callsign = namer("{date}_{callsign}_{seq}", "R1", TargetFs::Fat);
+        assert_eq!(render(&with_callsign), "2024-05-06_R1_007");
+
+        let without = namer("{date}_{callsign}_{seq}", "", TargetFs::Fat);
+        assert_eq!(render(&without), "2024-05-06_007");
+
+        let leading = namer("{callsign}-{time:%H%M}", "", TargetFs::Fat);
+        assert_eq!(render(&leading), "0708");
+
+        let subdir = namer("{source}/{callsign}_{profile}", "", TargetFs::Fat);
+        assert_eq!(render(&subdir), "v4l2/mkv");
+    }
+
+    #[test]
+    fn sanitize_keeps_literal_separators() {
+        let namer = namer("{date}", "", TargetFs::Fat);
+        assert_eq!(namer.sanitize("a__b"), "a__b");
+        assert_eq!(namer.sanitize("__a_ "), "a");
+    }
+
+    #[test]
+    fn sanitize_replaces_fat_forbidden_chars() {
+        let fat = namer("{date}", "", TargetFs::Fat);
+        assert_eq!(fat.sanitize("a:b|c?"), "a_b_c");
+        assert_eq!(fat.sanitize("a\tb"), "a_b");
+
+        let posix = namer("{date}", "", TargetFs::Posix);
+        assert_eq!(posix.sanitize("a:b|c?"), "a:b|c?");
+        assert_eq!(posix.sanitize("a\0b"), "a_b");
+    }
+
+    #[test]
+    fn sanitize_escapes_fat_reserved_names() {
+        let fat = namer("{date}", "", TargetFs::Fat);
+        assert_eq!(fat.sanitize("CON"), "_CON");
+        assert_eq!(fat.sanitize("lpt1.mkv"), "_lpt1.mkv");
+        assert_eq!(fat.sanitize("COM10"), "COM10");
+        assert_eq!(fat.sanitize("CONSOLE"), "CONSOLE");
+
+        let posix = namer("{date}", "", TargetFs::Posix);
+        assert_eq!(posix.sanitize("CON"), "CON");
+    }
+
+    #[test]
+    fn sanitize_truncates_at_char_boundary() {
+        let namer = namer("{date}", "", TargetFs::Fat);
+
+        let name = namer.sanitize(&"я".repeat(150));
+        assert_eq!(name.len(), MAX_NAME_LEN);
+        assert_eq!(name, "я".repeat(100));
+
+        let name = namer.sanitize(&format!("a{}", "я".repeat(150)));
+        assert_eq!(name.len(), MAX_NAME_LEN - 1);
+
+        let name = namer.sanitize(&format!("{}. {}", "a".repeat(MAX_NAME_LEN - 2), "b"));
+        assert_eq!(name, "a".repeat(MAX_NAME_LEN - 2));
+    }
+
+    /// Каталог записей во временном каталоге, удаляется после теста
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("ncy_naming_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn namer(&self, template: &str) -> FileNamer {
+            let config = NamingConfig {
+                template: template.to_string(),
+                callsign: String::from("cam"),
+                ..NamingConfig::default()
+            };
+            FileNamer::new(&self.0, &config).unwrap()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn collision_adds_suffix() {
+        let dir = TestDir::new("suffix");
+        let namer = dir.namer("{callsign}");
+
+        let first = namer.recording_path("v4l2", "mkv", "mkv").unwrap();
+        assert_eq!(first, dir.0.join("cam.mkv"));
+        fs::write(&first, b"").unwrap();
+
+        let second = namer.recording_path("v4l2", "mkv", "mkv").unwrap();
+        assert_eq!(second, dir.0.join("cam_2.mkv"));
+
+        // Каталог сессии тоже занимает имя
+        fs::create_dir(dir.0.join("cam_2")).unwrap();
+        let third = namer.recording_path("v4l2", "mkv", "mkv").unwrap();
+        assert_eq!(third, dir.0.join("cam_3.mkv"));
+    }
+
+    #[test]
+    fn collision_increments_seq() {
+        let dir = TestDir::new("seq");
+        let namer = dir.namer("{callsign}_{seq:2}");
+
+        let first = namer.recording_path("v4l2", "mkv", "mkv").unwrap();
+        assert_eq!(first, dir.0.join("cam_01.mkv"));
+        fs::write(&first, b"").unwrap();
+
+        let second = namer.recording_path("v4l2", "mkv", "mkv").unwrap();
+        assert_eq!(second, dir.0.join("cam_02.mkv"));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::session::Session;
+use crate::sidecar;
+use gstreamer::prelude::*;
+use gstreamer::{ClockTime, Element, MessageView, Pad, Pipeline, SeekFlags, SeekType, State};
+use gtk4::gdk;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Длительность кадра, если частота кадров неизвестна
+const DEFAULT_FRAME: ClockTime = ClockTime::from_mseconds(40);
+
+/// Метка, к которой можно перейти при просмотре
+#[derive(Clone, Debug)]
+pub struct Marker {
+    pub position: ClockTime,
+    pub label: Option<String>,
+}
+
+/// Что произошло в pipeline просмотра
+#[derive(Debug)]
+pub enum PlayerEvent {
+    /// Дошли до конца записи
+    Finished,
+    /// Метки прочитаны из оглавления файла
+    MarkersChanged,
+    Error(String),
+}
+
+/// Отдельный pipeline для просмотра записи. Картинка выводится через
+/// gtk4paintablesink, как и в живом просмотре.
+pub struct Player {
+    path: PathBuf,
+    pipeline: Pipeline,
+    sink: Element,
+    rate: f64,
+    markers: Vec<Marker>,
+}
+
+impl Player {
+    /// Открывает файл или каталог сессии и ставит просмотр на паузу в начале
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        // Сегменты сессии splitmuxsrc склеивает в один поток
+        let source = if path.is_dir() {
+            let session = Session::open(path)?;
+            format!(
+                "splitmuxsrc name=src location=\"{}\"",
+                session.segment_glob()
+            )
+        } else {
+            format!(
+                "filesrc location=\"{}\" ! decodebin name=src",
+                path.display()
+            )
+        };
+        let description = format!(
+            "{} videoconvert name=vconv ! gtk4paintablesink name=playsink",
+            source
+        );
+        println!("Просмотр: {}", description);
+
+        let pipeline = gstreamer::parse::launch(&description)?
+            .dynamic_cast::<Pipeline>()
+            .map_err(|_| "не удалось создать pipeline")?;
+        let src = pipeline.by_name("src").ok_or("нет источника")?;
+        let convert = pipeline.by_name("vconv").ok_or("нет videoconvert")?;
+        let sink = pipeline
+            .by_name("playsink")
+            .ok_or("нет gtk4paintablesink")?;
+        connect_streams(&pipeline, &src, &convert);
+
+        let markers = sidecar::load(path)
+            .map(|data| {
+                data.bookmarks
+                    .into_iter()
+                    .map(|bookmark| Marker {
+                        position: ClockTime::from_mseconds(bookmark.offset_ms),
+                        label: bookmark.label,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        pipeline.set_state(State::Paused)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            pipeline,
+            sink,
+            rate: 1.0,
+            markers,
+        })
+    }
+
+    /// Файл или каталог сессии, который сейчас открыт
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn paintable(&self) -> gdk::Paintable {
+        self.sink.property::<gdk::Paintable>("paintable")
+    }
+
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    pub fn play(&self) {
+        // После конца записи просмотр начинается сначала
+        if let (Some(position), Some(duration)) = (self.position(), self.duration())
+            && position >= duration
+        {
+            self.seek(ClockTime::ZERO);
+        }
+        let _ = self.pipeline.set_state(State::Playing);
+    }
+
+    pub fn pause(&self) {
+        let _ = self.pipeline.set_state(State::Paused);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.pipeline.current_state() == State::Playing
+    }
+
+    pub fn position(&self) -> Option<ClockTime> {
+        self.pipeline.query_position::<ClockTime>()
+    }
+
+    pub fn duration(&self) -> Option<ClockTime> {
+        self.pipeline.query_duration::<ClockTime>()
+    }
+
+    pub fn seek(&self, position: ClockTime) {
+        if let Err(e) = self.pipeline.seek(
+            self.rate,
+            SeekFlags::FLUSH | SeekFlags::ACCURATE,
+            SeekType::Set,
+            position,
+            SeekType::None,
+            ClockTime::NONE,
+        ) {
+            println!("Не удалось перейти к {}: {}", position, e);
+        }
+    }
+
+    /// Скорость просмотра, 1.0 - обычная. Звук сохраняет высоту тона.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
+        if let Some(position) = self.position() {
+            self.seek(position);
+        }
+    }
+
+    /// Шаг на один кадр с остановкой просмотра
+    pub fn step(&self, forward: bool) {
+        self.pause();
+        if forward {
+            self.sink.send_event(gstreamer::event::Step::new(
+                gstreamer::format::Buffers::from_u64(1),
+                self.rate,
+                true,
+                false,
+            ));
+        } else if let Some(position) = self.position() {
+            self.seek(position.saturating_sub(self.frame_duration()));
+        }
+    }
+
+    fn frame_duration(&self) -> ClockTime {
+        self.sink
+            .static_pad("sink")
+            .and_then(|pad| pad.current_caps())
+            .and_then(|caps| gstreamer_video::VideoInfo::from_caps(&caps).ok())
+            .filter(|info| info.fps().numer() > 0)
+            .map(|info| {
+                ClockTime::from_nseconds(
+                    1_000_000_000 * info.fps().denom() as u64 / info.fps().numer() as u64,
+                )
+            })
+            .unwrap_or(DEFAULT_FRAME)
+    }
+
+    /// Разбирает сообщения pipeline, вызывается по таймеру
+    pub fn poll(&mut self) -> Option<PlayerEvent> {
+        let bus = self.pipeline.bus()?;
+        while let Some(msg) = bus.pop() {
+            match msg.view() {
+                MessageView::Eos(_) => {
+                    self.pause();
+                    return Some(PlayerEvent::Finished);
+                }
+                MessageView::Error(err) => {
+                    println!("Ошибка просмотра: {} ({:?})", err.error(), err.debug());
+                    return Some(PlayerEvent::Error(err.error().to_string()));
+                }
+                // Без файла метаданных метки берутся из глав Matroska
+                MessageView::Toc(toc) if self.markers.is_empty() => {
+                    let (toc, _) = toc.toc();
+                    self.markers = markers_from_toc(&toc);
+                    if !self.markers.is_empty() {
+                        return Some(PlayerEvent::MarkersChanged);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+fn markers_from_toc(toc: &gstreamer::TocRef) -> Vec<Marker> {
+    fn collect(entries: Vec<gstreamer::TocEntry>, markers: &mut Vec<Marker>) {
+        for entry in entries {
+            if entry.entry_type() == gstreamer::TocEntryType::Chapter
+                && let Some((start, _)) = entry.start_stop_times()
+            {
+                markers.push(Marker {
+                    position: ClockTime::from_nseconds(start.max(0) as u64),
+                    label: entry.tags().and_then(|tags| {
+                        tags.get::<gstreamer::tags::Title>()
+                            .map(|title| title.get().to_string())
+                    }),
+                });
+            }
+            collect(entry.sub_entries(), markers);
+        }
+    }
+
+    let mut markers = Vec::new();
+    collect(toc.entries(), &mut markers);
+    markers.sort_by_key(|m| m.position);
+    markers
+}
+
+/// Подключает потоки, которые появляются на `element`: несжатое видео к
+/// `video`, несжатый звук к новому выходу звука, сжатые потоки
+/// (от splitmuxsrc) - через decodebin. Лишние потоки выбрасываются.
+fn connect_streams(pipeline: &Pipeline, element: &Element, video: &Element) {
+    element.connect_pad_added({
+        let pipeline = pipeline.downgrade();
+        let video = video.clone();
+        move |_, pad| {
+            let Some(pipeline) = pipeline.upgrade() else {
+                return;
+            };
+            if let Err(e) = link_stream(&pipeline, pad, &video) {
+                println!("Не удалось подключить поток {}: {}", pad.name(), e);
+            }
+        }
+    });
+}
+
+fn link_stream(pipeline: &Pipeline, pad: &Pad, video: &Element) -> Result<(), Box<dyn Error>> {
+    let caps = pad.current_caps().unwrap_or_else(|| pad.query_caps(None));
+    let media = caps
+        .structure(0)
+        .map(|s| s.name().to_string())
+        .unwrap_or_default();
+
+    let video_pad = video.static_pad("sink").filter(|p| !p.is_linked());
+    let sink_pad = match (media.as_str(), video_pad) {
+        ("video/x-raw", Some(video_pad)) => video_pad,
+        ("video/x-raw", None) => {
+            let fakesink = gstreamer::ElementFactory::make("fakesink").build()?;
+            add_element(pipeline, &fakesink)?
+        }
+        ("audio/x-raw", _) => {
+            let bin = gstreamer::parse::bin_from_description(
+                "queue ! audioconvert ! audioresample ! scaletempo ! autoaudiosink",
+                true,
+            )?;
+            add_element(pipeline, bin.upcast_ref())?
+        }
+        (media, _) if media.starts_with("video/") || media.starts_with("audio/") => {
+            let decodebin = gstreamer::ElementFactory::make("decodebin").build()?;
+            connect_streams(pipeline, &decodebin, video);
+            add_element(pipeline, &decodebin)?
+        }
+        _ => {
+            let fakesink = gstreamer::ElementFactory::make("fakesink").build()?;
+            add_element(pipeline, &fakesink)?
+        }
+    };
+    pad.link(&sink_pad)?;
+    Ok(())
+}
+
+/// Добавляет элемент в работающий pipeline и возвращает его вход
+fn add_element(pipeline: &Pipeline, element: &Element) -> Result<Pad, Box<dyn Error>> {
+    pipeline.add(element)?;
+    element.sync_state_with_parent()?;
+    Ok(element.static_pad("sink").ok_or("у элемента нет входа")?)
+}
@@ -0,0 +1,418 @@
+use crate::config::CameraConfig;
+use crate::export::{ExportRequest, export_clip};
+use crate::player::{Marker, Player, PlayerEvent};
+use crate::profile::RecordingProfile;
+use gstreamer::ClockTime;
+use gtk4::prelude::*;
+use gtk4::{
+    Box as GtkBox, Button, CheckButton, DropDown, Label, Orientation, Picture, Scale, gio, glib,
+};
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Скорости просмотра в выпадающем списке
+const SPEEDS: [(f64, &str); 5] = [
+    (0.25, "0.25x"),
+    (0.5, "0.5x"),
+    (1.0, "1x"),
+    (2.0, "2x"),
+    (4.0, "4x"),
+];
+
+/// Индекс обычной скорости в `SPEEDS`
+const NORMAL_SPEED: u32 = 2;
+
+/// Обработчик готового фрагмента, получает путь к новому файлу
+type ExportCallback = Rc<dyn Fn(&Path)>;
+
+/// Экран просмотра записи: картинка, полоса перемотки и управление
+pub struct PlayerView {
+    root: GtkBox,
+    title: Label,
+    picture: Picture,
+    scale: Scale,
+    position_label: Label,
+    play_button: Button,
+    speed: DropDown,
+    markers: DropDown,
+    live_button: Button,
+    trim_label: Label,
+    reencode: CheckButton,
+    export_button: Button,
+    /// Границы фрагмента для экспорта
+    trim_start: Cell<Option<ClockTime>>,
+    trim_end: Cell<Option<ClockTime>>,
+    /// Камера и текущий профиль записи нужны экспорту, если у записи нет
+    /// файла метаданных
+    camera: CameraConfig,
+    current_profile: Box<dyn Fn() -> (String, RecordingProfile)>,
+    player: RefCell<Option<Player>>,
+    timer: RefCell<Option<glib::SourceId>>,
+    on_exported: RefCell<Option<ExportCallback>>,
+}
+
+impl PlayerView {
+    pub fn new(
+        camera: CameraConfig,
+        current_profile: impl Fn() -> (String, RecordingProfile) + 'static,
+    ) -> Rc<Self> {
+        let root = GtkBox::new(Orientation::Vertical, 5);
+        root.add_css_class("player");
+        root.set_hexpand(true);
+        root.set_vexpand(true);
+
+        let title = Label::new(None);
+        title.add_css_class("player-title");
+        root.append(&title);
+
+        let picture = Picture::new();
+        picture.set_vexpand(true);
+        root.append(&picture);
+
+        let seek_row = GtkBox::new(Orientation::Horizontal, 5);
+        let scale = Scale::with_range(Orientation::Horizontal, 0.0, 1.0, 0.1);
+        scale.set_hexpand(true);
+        scale.set_draw_value(false);
+        let position_label = Label::new(Some("00:00:00 / 00:00:00"));
+        position_label.add_css_class("player-position");
+        seek_row.append(&scale);
+        seek_row.append(&position_label);
+        root.append(&seek_row);
+
+        let controls = GtkBox::new(Orientation::Horizontal, 5);
+        let step_back = Button::with_label("Кадр назад");
+        let play_button = Button::with_label("Пуск");
+        let step_forward = Button::with_label("Кадр вперёд");
+        let speed = DropDown::from_strings(&SPEEDS.map(|(_, name)| name));
+        speed.set_selected(NORMAL_SPEED);
+        let markers = DropDown::from_strings(&[]);
+        markers.set_hexpand(true);
+        let jump = Button::with_label("К метке");
+        let live_button = Button::with_label("Прямой эфир");
+        live_button.add_css_class("live-button");
+        for widget in [&step_back, &play_button, &step_forward, &jump, &live_button] {
+            widget.add_css_class("player-button");
+        }
+        controls.append(&step_back);
+        controls.append(&play_button);
+        controls.append(&step_forward);
+        controls.append(&speed);
+        controls.append(&markers);
+        controls.append(&jump);
+        controls.append(&live_button);
+        root.append(&controls);
+
+        // Вырезание фрагмента: начало и конец по текущему положению
+        let trim = GtkBox::new(Orientation::Horizontal, 5);
+        let set_start = Button::with_label("Начало");
+        let set_end = Button::with_label("Конец");
+        let trim_label = Label::new(None);
+        trim_label.add_css_class("player-position");
+        trim_label.set_hexpand(true);
+        let reencode = CheckButton::with_label("Точно по кадрам (перекодировать)");
+        let export_button = Button::with_label("Экспорт");
+        for widget in [&set_start, &set_end, &export_button] {
+            widget.add_css_class("player-button");
+        }
+        trim.append(&set_start);
+        trim.append(&set_end);
+        trim.append(&trim_label);
+        trim.append(&reencode);
+        trim.append(&export_button);
+        root.append(&trim);
+
+        let view = Rc::new(Self {
+            root,
+            title,
+            picture,
+            scale,
+            position_label,
+            play_button,
+            speed,
+            markers,
+            live_button,
+            trim_label,
+            reencode,
+            export_button,
+            trim_start: Cell::new(None),
+            trim_end: Cell::new(None),
+            camera,
+            current_profile: Box::new(current_profile),
+            player: RefCell::new(None),
+            timer: RefCell::new(None),
+            on_exported: RefCell::new(None),
+        });
+
+        // change-value приходит только от пользователя, не от set_value
+        view.scale.connect_change_value({
+            let view = Rc::downgrade(&view);
+            move |_, _, value| {
+                if let Some(view) = view.upgrade() {
+                    view.with_player(|player| {
+                        player.seek(ClockTime::from_mseconds((value.max(0.0) * 1000.0) as u64))
+                    });
+                }
+                glib::Propagation::Proceed
+            }
+        });
+
+        view.play_button.connect_clicked({
+            let view = Rc::downgrade(&view);
+            move |_| {
+                if let Some(view) = view.upgrade() {
+                    view.with_player(|player| {
+                        if player.is_playing() {
+                            player.pause();
+                        } else {
+                            player.play();
+                        }
+                    });
+                }
+            }
+        });
+
+        for (button, forward) in [(&step_back, false), (&step_forward, true)] {
+            let view = Rc::downgrade(&view);
+            button.connect_clicked(move |_| {
+                if let Some(view) = view.upgrade() {
+                    view.with_player(|player| player.step(forward));
+                }
+            });
+        }
+
+        view.speed.connect_selected_notify({
+            let view = Rc::downgrade(&view);
+            move |speed| {
+                let Some((rate, _)) = SPEEDS.get(speed.selected() as usize) else {
+                    return;
+                };
+                if let Some(view) = view.upgrade() {
+                    view.with_player(|player| player.set_rate(*rate));
+                }
+            }
+        });
+
+        jump.connect_clicked({
+            let view = Rc::downgrade(&view);
+            move |_| {
+                let Some(view) = view.upgrade() else {
+                    return;
+                };
+                let index = view.markers.selected() as usize;
+                view.with_player(|player| {
+                    if let Some(marker) = player.markers().get(index) {
+                        player.seek(marker.position);
+                    }
+                });
+            }
+        });
+
+        for (button, is_start) in [(&set_start, true), (&set_end, false)] {
+            let view = Rc::downgrade(&view);
+            button.connect_clicked(move |_| {
+                let Some(view) = view.upgrade() else {
+                    return;
+                };
+                let position = view.player.borrow().as_ref().and_then(Player::position);
+                if is_start {
+                    view.trim_start.set(position);
+                } else {
+                    view.trim_end.set(position);
+                }
+                view.show_trim();
+            });
+        }
+
+        view.export_button.connect_clicked({
+            let view = Rc::downgrade(&view);
+            move |_| {
+                if let Some(view) = view.upgrade() {
+                    view.export();
+                }
+            }
+        });
+
+        view
+    }
+
+    pub fn widget(&self) -> &GtkBox {
+        &self.root
+    }
+
+    /// Кнопка возврата к камере
+    pub fn connect_live(&self, callback: impl Fn() + 'static) {
+        self.live_button.connect_clicked(move |_| callback());
+    }
+
+    /// Фрагмент сохранён
+    pub fn connect_exported(&self, callback: impl Fn(&Path) + 'static) {
+        *self.on_exported.borrow_mut() = Some(Rc::new(callback));
+    }
+
+    /// Открывает запись и сразу начинает просмотр
+    pub fn open(self: &Rc<Self>, path: &Path) -> Result<(), Box<dyn Error>> {
+        self.close();
+
+        let player = Player::open(path)?;
+        self.picture.set_paintable(Some(&player.paintable()));
+        self.title
+            .set_label(&path.file_name().unwrap_or_default().to_string_lossy());
+        self.speed.set_selected(NORMAL_SPEED);
+        self.show_markers(player.markers());
+        self.scale.set_value(0.0);
+        self.trim_start.set(None);
+        self.trim_end.set(None);
+        self.show_trim();
+        player.play();
+        *self.player.borrow_mut() = Some(player);
+
+        let view = Rc::downgrade(self);
+        let timer =
+            glib::timeout_add_local(Duration::from_millis(200), move || match view.upgrade() {
+                Some(view) => {
+                    view.update();
+                    glib::ControlFlow::Continue
+                }
+                None => glib::ControlFlow::Break,
+            });
+        *self.timer.borrow_mut() = Some(timer);
+        Ok(())
+    }
+
+    /// Останавливает просмотр и освобождает pipeline
+    pub fn close(&self) {
+        if let Some(timer) = self.timer.borrow_mut().take() {
+            timer.remove();
+        }
+        self.player.borrow_mut().take();
+        self.picture.set_paintable(None::<&gtk4::gdk::Paintable>);
+    }
+
+    fn show_trim(&self) {
+        let start = self
+            .trim_start
+            .get()
+            .map_or(String::from("начало"), format_time);
+        let end = self
+            .trim_end
+            .get()
+            .map_or(String::from("конец"), format_time);
+        self.trim_label.set_label(&format!("{} - {}", start, end));
+    }
+
+    /// Экспорт фрагмента в фоне, результат показывается в заголовке
+    fn export(&self) {
+        let request = {
+            let player = self.player.borrow();
+            let Some(player) = player.as_ref() else {
+                return;
+            };
+            let (profile_name, profile) = (self.current_profile)();
+            ExportRequest {
+                source: player.path().to_path_buf(),
+                start: self.trim_start.get().unwrap_or(ClockTime::ZERO),
+                end: self
+                    .trim_end
+                    .get()
+                    .or_else(|| player.duration())
+                    .unwrap_or(ClockTime::ZERO),
+                reencode: self.reencode.is_active(),
+                camera: self.camera.clone(),
+                profile_name,
+                profile,
+            }
+        };
+
+        self.export_button.set_sensitive(false);
+        self.title.set_label("Экспорт фрагмента...");
+        let button = self.export_button.clone();
+        let title = self.title.clone();
+        let on_exported = self.on_exported.borrow().clone();
+        glib::spawn_future_local(async move {
+            let result =
+                gio::spawn_blocking(move || export_clip(&request).map_err(|e| e.to_string()))
+                    .await
+                    .unwrap_or_else(|_| Err(String::from("поток экспорта завершился с ошибкой")));
+
+            button.set_sensitive(true);
+            match result {
+                Ok(path) => {
+                    title.set_label(&format!(
+                        "Фрагмент сохранён: {}",
+                        path.file_name().unwrap_or_default().to_string_lossy()
+                    ));
+                    if let Some(on_exported) = on_exported {
+                        on_exported(&path);
+                    }
+                }
+                Err(e) => {
+                    println!("Ошибка экспорта: {}", e);
+                    title.set_label(&format!("Ошибка экспорта: {}", e));
+                }
+            }
+        });
+    }
+
+    fn with_player(&self, action: impl FnOnce(&mut Player)) {
+        if let Some(player) = self.player.borrow_mut().as_mut() {
+            action(player);
+        }
+    }
+
+    fn show_markers(&self, markers: &[Marker]) {
+        let names: Vec<String> = markers
+            .iter()
+            .enumerate()
+            .map(|(i, marker)| {
+                let label = marker
+                    .label
+                    .clone()
+                    .unwrap_or_else(|| format!("Метка {}", i + 1));
+                format!("{} {}", format_time(marker.position), label)
+            })
+            .collect();
+        let model = gtk4::StringList::new(&names.iter().map(String::as_str).collect::<Vec<_>>());
+        self.markers.set_model(Some(&model));
+        self.markers.set_sensitive(!markers.is_empty());
+    }
+
+    /// Обновляет полосу перемотки и разбирает сообщения pipeline
+    fn update(&self) {
+        let mut player = self.player.borrow_mut();
+        let Some(player) = player.as_mut() else {
+            return;
+        };
+
+        match player.poll() {
+            Some(PlayerEvent::MarkersChanged) => self.show_markers(player.markers()),
+            Some(PlayerEvent::Error(message)) => self.title.set_label(&message),
+            Some(PlayerEvent::Finished) | None => {}
+        }
+
+        let position = player.position().unwrap_or(ClockTime::ZERO);
+        let duration = player.duration().unwrap_or(ClockTime::ZERO);
+        if duration > ClockTime::ZERO {
+            self.scale
+                .set_range(0.0, duration.mseconds() as f64 / 1000.0);
+        }
+        self.scale.set_value(position.mseconds() as f64 / 1000.0);
+        self.position_label.set_label(&format!(
+            "{} / {}",
+            format_time(position),
+            format_time(duration)
+        ));
+        self.play_button.set_label(if player.is_playing() {
+            "Пауза"
+        } else {
+            "Пуск"
+        });
+    }
+}
+
+fn format_time(time: ClockTime) -> String {
+    let secs = time.seconds();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,360 @@
+use crate::audio::AudioCodec;
+use crate::config::SegmentConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Кодек записи
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    X264,
+    X265,
+    Vp8,
+    Vp9,
+    Av1,
+    Mjpeg,
+}
+
+/// Контейнер файла записи
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    Mp4,
+    Mkv,
+    Ts,
+    Webm,
+    Avi,
+}
+
+impl Container {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::Mkv => "mkv",
+            Container::Ts => "ts",
+            Container::Webm => "webm",
+            Container::Avi => "avi",
+        }
+    }
+
+    /// Контейнер по расширению файла
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "mp4" => Some(Container::Mp4),
+            "mkv" => Some(Container::Mkv),
+            "ts" => Some(Container::Ts),
+            "webm" => Some(Container::Webm),
+            "avi" => Some(Container::Avi),
+            _ => None,
+        }
+    }
+
+    /// Имя фабрики muxer, для свойства `muxer-factory` у splitmuxsink
+    pub fn muxer_factory(&self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4mux",
+            Container::Mkv => "matroskamux",
+            Container::Ts => "mpegtsmux",
+            Container::Webm => "webmmux",
+            Container::Avi => "avimux",
+        }
+    }
+
+    /// Файл Matroska начинается с нулевого времени, от него считаются
+    /// главы меток и фрагменты
+    fn offset_to_zero(&self) -> bool {
+        matches!(self, Container::Mkv | Container::Webm)
+    }
+
+    /// Описание muxer для пересборки и экспорта файлов
+    pub fn muxer_element(&self) -> String {
+        if self.offset_to_zero() {
+            format!("{} offset-to-zero=true", self.muxer_factory())
+        } else {
+            self.muxer_factory().to_string()
+        }
+    }
+
+    /// Свойства muxer для splitmuxsink: `muxer-factory` принимает только
+    /// имя фабрики
+    fn splitmux_properties(&self) -> &'static str {
+        if self.offset_to_zero() {
+            " muxer-properties=\"properties,offset-to-zero=(boolean)true\""
+        } else {
+            ""
+        }
+    }
+
+    /// Описание muxer для pipeline. С `crash_safe` файл пишется так, чтобы
+    /// после обрыва записи всё до последней секунды оставалось читаемым:
+    /// MP4 фрагментами по секунде, Matroska кластерами не длиннее секунды.
+    /// Matroska начинается с нулевого времени, от него считаются главы меток.
+    fn muxer(&self, crash_safe: bool) -> &'static str {
+        match self {
+            Container::Mp4 if crash_safe => "mp4mux streamable=true fragment-duration=1000",
+            Container::Mkv if crash_safe => {
+                "matroskamux offset-to-zero=true max-cluster-duration=1000000000"
+            }
+            Container::Webm if crash_safe => {
+                "webmmux offset-to-zero=true max-cluster-duration=1000000000"
+            }
+            Container::Mp4 => "mp4mux streamable=true fragment-duration=1",
+            Container::Mkv => "matroskamux",
+            Container::Ts => "mpegtsmux",
+            Container::Webm => "webmmux",
+            Container::Avi => "avimux",
+        }
+    }
+
+    /// Можно ли писать контейнер с защитой от обрыва
+    pub fn supports_crash_safe(&self) -> bool {
+        matches!(self, Container::Mp4 | Container::Mkv | Container::Webm)
+    }
+
+    /// Может ли контейнер хранить звук этого кодека
+    pub fn supports_audio(&self, codec: AudioCodec) -> bool {
+        match self {
+            Container::Mp4 | Container::Mkv => true,
+            Container::Ts | Container::Avi => codec == AudioCodec::Aac,
+            Container::Webm => codec == AudioCodec::Opus,
+        }
+    }
+
+    /// Может ли контейнер хранить видео этого кодека
+    pub fn supports(&self, codec: Codec) -> bool {
+        match self {
+            Container::Mp4 => {
+                matches!(codec, Codec::X264 | Codec::X265 | Codec::Av1 | Codec::Mjpeg)
+            }
+            Container::Mkv => true,
+            Container::Ts => matches!(codec, Codec::X264 | Codec::X265),
+            Container::Webm => matches!(codec, Codec::Vp8 | Codec::Vp9 | Codec::Av1),
+            Container::Avi => matches!(codec, Codec::X264 | Codec::Mjpeg),
+        }
+    }
+}
+
+/// Профиль записи: кодек, контейнер и параметры кодирования
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingProfile {
+    pub codec: Codec,
+    pub container: Container,
+    /// Битрейт, кбит/с
+    pub bitrate: u32,
+    /// Интервал ключевых кадров, в кадрах
+    pub keyframe_interval: u32,
+    /// Уменьшение размера кадра перед кодированием
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// Писать файл так, чтобы он читался после аварийного завершения
+    pub crash_safe: bool,
+}
+
+impl Default for RecordingProfile {
+    fn default() -> Self {
+        Self {
+            codec: Codec::X264,
+            container: Container::Mp4,
+            bitrate: 2048,
+            keyframe_interval: 30,
+            width: None,
+            height: None,
+            crash_safe: false,
+        }
+    }
+}
+
+impl RecordingProfile {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.container.supports(self.codec) {
+            return Err(format!(
+                "контейнер {:?} не поддерживает кодек {:?}",
+                self.container, self.codec
+            ));
+        }
+        if self.crash_safe && !self.container.supports_crash_safe() {
+            return Err(format!(
+                "crash_safe поддерживают только mp4, mkv и webm, а не {:?}",
+                self.container
+            ));
+        }
+        if self.bitrate == 0 && self.codec != Codec::Mjpeg {
+            return Err(String::from("битрейт должен быть больше нуля"));
+        }
+        if self.keyframe_interval == 0 {
+            return Err(String::from(
+                "интервал ключевых кадров должен быть больше нуля",
+            ));
+        }
+        match (self.width, self.height) {
+            (Some(w), Some(h)) if w <= 0 || h <= 0 => {
+                Err(format!("размер {}x{} должен быть положительным", w, h))
+            }
+            (Some(_), None) | (None, Some(_)) => Err(String::from(
+                "для уменьшения нужно задать и width, и height",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Можно ли писать кадры камеры как есть: MJPEG без уменьшения размера
+    pub fn is_passthrough(&self, mjpeg_source: bool) -> bool {
+        mjpeg_source && self.codec == Codec::Mjpeg && self.width.is_none()
+    }
+
+    /// Кодер со всеми параметрами, на выходе - сжатый поток для muxer
+    fn encoder(&self) -> String {
+        let kbps = self.bitrate;
+        let keyint = self.keyframe_interval;
+        match self.codec {
+            Codec::X264 => format!(
+                "x264enc tune=zerolatency speed-preset=superfast bitrate={} key-int-max={} ! \
+                video/x-h264,profile=main ! h264parse",
+                kbps, keyint
+            ),
+            Codec::X265 => format!(
+                "x265enc tune=zerolatency speed-preset=superfast bitrate={} key-int-max={} ! \
+                h265parse",
+                kbps, keyint
+            ),
+            Codec::Vp8 => format!(
+                "vp8enc deadline=1 target-bitrate={} keyframe-max-dist={}",
+                kbps * 1000,
+                keyint
+            ),
+            Codec::Vp9 => format!(
+                "vp9enc deadline=1 cpu-used=8 target-bitrate={} keyframe-max-dist={}",
+                kbps * 1000,
+                keyint
+            ),
+            Codec::Av1 => format!(
+                "av1enc usage-profile=realtime cpu-used=8 target-bitrate={} \
+                keyframe-max-dist={} ! av1parse",
+                kbps, keyint
+            ),
+            Codec::Mjpeg => String::from("jpegenc quality=85"),
+        }
+    }
+
+    /// Часть ветки от tee до сжатого потока. При `passthrough` на входе
+    /// JPEG кадры камеры, они только разбираются jpegparse.
+    pub fn encode_description(&self, passthrough: bool) -> String {
+        if passthrough {
+            return String::from("queue ! jpegparse");
+        }
+
+        let scale = match (self.width, self.height) {
+            (Some(w), Some(h)) => format!("videoscale ! video/x-raw,width={},height={} ! ", w, h),
+            _ => String::new(),
+        };
+        format!("queue ! videoconvert ! {}{}", scale, self.encoder())
+    }
+
+    /// Часть ветки от сжатого потока до файла
+    pub fn mux_description(&self, file_path: &str) -> String {
+        format!(
+            "{} name=mux ! filesink location=\"{}\" sync=false",
+            self.container.muxer(self.crash_safe),
+            file_path
+        )
+    }
+
+    /// Часть ветки от сжатого потока до файлов сегментов. splitmuxsink
+    /// режет только по ключевым кадрам, поэтому каждый файл воспроизводится
+    /// отдельно. `location` - шаблон с номером, например session_%04d.mp4.
+    pub fn segment_mux_description(&self, location: &str, segments: &SegmentConfig) -> String {
+        // Запрос ключевого кадра работает только при ограничении по времени
+        let keyframe_requests = segments.max_seconds > 0 && segments.max_mb == 0;
+        format!(
+            "splitmuxsink name=mux location=\"{}\" start-index=1 muxer-factory={}{} \
+            max-size-time={} max-size-bytes={} send-keyframe-requests={}",
+            location,
+            self.container.muxer_factory(),
+            self.container.splitmux_properties(),
+            segments.max_seconds * 1_000_000_000,
+            segments.max_mb * 1024 * 1024,
+            keyframe_requests
+        )
+    }
+}
+
+/// Набор профилей по умолчанию: обычный, маленький для просмотра,
+/// MJPEG без перекодирования и архивный
+pub fn default_profiles() -> BTreeMap<String, RecordingProfile> {
+    let mut profiles = BTreeMap::new();
+    profiles.insert(String::from("default"), RecordingProfile::default());
+    profiles.insert(
+        String::from("review"),
+        RecordingProfile {
+            bitrate: 512,
+            keyframe_interval: 50,
+            width: Some(640),
+            height: Some(360),
+            ..RecordingProfile::default()
+        },
+    );
+    profiles.insert(
+        String::from("mjpeg"),
+        RecordingProfile {
+            codec: Codec::Mjpeg,
+            container: Container::Mkv,
+            bitrate: 0,
+            ..RecordingProfile::default()
+        },
+    );
+    profiles.insert(
+        String::from("archive"),
+        RecordingProfile {
+            container: Container::Mkv,
+            bitrate: 8192,
+            crash_safe: true,
+            ..RecordingProfile::default()
+        },
+    );
+    profiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gstreamer::prelude::*;
+
+    #[test]
+    fn segment_branch_parses_for_every_profile() {
+        gstreamer::init().unwrap();
+        let segments = SegmentConfig {
+            max_seconds: 60,
+            max_mb: 0,
+        };
+
+        for (name, profile) in default_profiles() {
+            let description = format!(
+                "{} ! {}",
+                profile.encode_description(false),
+                profile.segment_mux_description("/tmp/session_%04d.mkv", &segments)
+            );
+            let bin = gstreamer::parse::bin_from_description(&description, true)
+                .unwrap_or_else(|e| panic!("профиль {}: {}: {}", name, description, e));
+            assert!(bin.by_name("mux").is_some());
+        }
+    }
+
+    #[test]
+    fn muxer_factory_is_a_plain_name() {
+        for container in [
+            Container::Mp4,
+            Container::Mkv,
+            Container::Ts,
+            Container::Webm,
+            Container::Avi,
+        ] {
+            assert!(!container.muxer_factory().contains(' '));
+            assert!(
+                container
+                    .muxer_element()
+                    .starts_with(container.muxer_factory())
+            );
+        }
+    }
+}
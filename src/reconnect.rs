@@ -0,0 +1,494 @@
+use crate::config::CameraConfig;
+use crate::discovery::device_info;
+use crate::rtp_stats::RtpStats;
+use crate::video_source::{JITTERBUFFER_NAME, JPEG_TAP_NAME, VideoSource};
+use gstreamer::prelude::*;
+use gstreamer::{
+    Bin, DeviceMonitor, Element, GhostPad, MessageView, Pad, PadProbeReturn, PadProbeType,
+    Pipeline, State,
+};
+use gtk4::glib;
+use std::cell::RefCell;
+use std::error::Error;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Максимальная пауза между попытками переподключения
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Есть ли сейчас кадры от источника
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SignalState {
+    Live,
+    NoSignal,
+}
+
+struct Inner {
+    pipeline: Pipeline,
+    /// input-selector перед `tee`
+    selector: Element,
+    /// Вход селектора с заставкой "NO SIGNAL"
+    fallback_pad: Pad,
+    /// Вход селектора, к которому подключён источник
+    source_pad: Option<Pad>,
+    /// tee для записи MJPEG без перекодирования, живёт дольше источника
+    jpeg_tee: Element,
+    camera: CameraConfig,
+    bin: Option<Bin>,
+    attempt: u32,
+    retry: Option<glib::SourceId>,
+    /// Время последнего кадра от источника
+    last_buffer: Arc<Mutex<Option<Instant>>>,
+    /// Когда источник был подключён, для поиска зависших сетевых потоков
+    attached_at: Option<Instant>,
+    /// Последнее сообщение о состоянии сетевого потока
+    stream_status: Option<String>,
+    state: SignalState,
+    monitor: Option<DeviceMonitor>,
+    on_state: Option<Rc<dyn Fn(SignalState)>>,
+}
+
+/// Держит источник видео отдельным bin на входе input-selector, чтобы его
+/// можно было пересоздать без остановки остального pipeline и ветки записи.
+/// Пока от источника нет кадров, селектор переключён на заставку, поэтому
+/// `tee` и запись получают видео без разрывов.
+#[derive(Clone)]
+pub struct SourceManager {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl SourceManager {
+    pub fn new(
+        pipeline: &Pipeline,
+        selector: &Element,
+        jpeg_tee: &Element,
+        camera: &CameraConfig,
+    ) -> Self {
+        let fallback_pad = selector
+            .static_pad("sink_0")
+            .expect("У input-selector нет входа с заставкой");
+        selector.set_property("active-pad", &fallback_pad);
+
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                pipeline: pipeline.clone(),
+                selector: selector.clone(),
+                fallback_pad,
+                source_pad: None,
+                jpeg_tee: jpeg_tee.clone(),
+                camera: camera.clone(),
+                bin: None,
+                attempt: 0,
+                retry: None,
+                last_buffer: Arc::new(Mutex::new(None)),
+                attached_at: None,
+                stream_status: None,
+                state: SignalState::NoSignal,
+                monitor: None,
+                on_state: None,
+            })),
+        }
+    }
+
+    fn from_weak(weak: &Weak<RefCell<Inner>>) -> Option<Self> {
+        weak.upgrade().map(|inner| Self { inner })
+    }
+
+    /// Вызывается при появлении и пропадании сигнала
+    pub fn connect_state_changed<F: Fn(SignalState) + 'static>(&self, f: F) {
+        self.inner.borrow_mut().on_state = Some(Rc::new(f));
+    }
+
+    /// Подключает источник и начинает следить за появлением устройств.
+    /// Если источник сейчас недоступен, переподключение идёт в фоне.
+    pub fn start(&self) {
+        self.start_monitor();
+
+        if let Err(e) = self.attach() {
+            println!("Не удалось подключить источник: {}", e);
+            self.detach();
+            self.schedule_retry();
+        }
+    }
+
+    fn start_monitor(&self) {
+        let monitor = DeviceMonitor::new();
+        monitor.add_filter(Some("Video/Source"), None);
+        match monitor.start() {
+            Ok(_) => self.inner.borrow_mut().monitor = Some(monitor),
+            Err(e) => println!("DeviceMonitor не запущен, hot-plug недоступен: {}", e),
+        }
+    }
+
+    /// Принадлежит ли элемент текущему bin источника
+    pub fn owns(&self, object: &gstreamer::Object) -> bool {
+        match &self.inner.borrow().bin {
+            Some(bin) => object.has_as_ancestor(bin),
+            None => false,
+        }
+    }
+
+    /// Ошибка источника: переключаемся на заставку, отключаем источник и
+    /// пробуем подключить его заново
+    pub fn handle_source_error(&self) {
+        self.set_state(SignalState::NoSignal);
+        self.detach();
+        self.schedule_retry();
+    }
+
+    /// Обрабатывает события DeviceMonitor и следит, идут ли кадры от
+    /// источника. Вызывается из того же таймера, что и разбор шины pipeline.
+    pub fn poll(&self) {
+        let live = {
+            let inner = self.inner.borrow();
+            let timeout = Duration::from_millis(inner.camera.no_signal_timeout_ms);
+            let fresh = inner
+                .last_buffer
+                .lock()
+                .unwrap()
+                .is_some_and(|t| t.elapsed() < timeout);
+            fresh && inner.bin.is_some()
+        };
+
+        if live {
+            self.inner.borrow_mut().attempt = 0;
+            self.set_state(SignalState::Live);
+        } else {
+            self.set_state(SignalState::NoSignal);
+        }
+
+        if self.is_stalled() {
+            println!("Источник не отдаёт кадры, переподключаемся");
+            self.handle_source_error();
+        }
+
+        let bus = match &self.inner.borrow().monitor {
+            Some(monitor) => monitor.bus(),
+            None => return,
+        };
+
+        while let Some(msg) = bus.pop() {
+            match msg.view() {
+                MessageView::DeviceAdded(added) if self.is_our_device(&added.device()) => {
+                    println!("Устройство источника подключено");
+                    if self.inner.borrow().bin.is_none() {
+                        self.cancel_retry();
+                        self.inner.borrow_mut().attempt = 0;
+                        self.reconnect();
+                    }
+                }
+                MessageView::DeviceRemoved(removed) if self.is_our_device(&removed.device()) => {
+                    println!("Устройство источника отключено");
+                    self.handle_source_error();
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Сетевой источник подключён, но давно не присылал кадров
+    fn is_stalled(&self) -> bool {
+        let inner = self.inner.borrow();
+        let (Some(timeout), Some(attached_at)) =
+            (inner.camera.source.stall_timeout(), inner.attached_at)
+        else {
+            return false;
+        };
+
+        let last_activity = inner.last_buffer.lock().unwrap().unwrap_or(attached_at);
+        inner.bin.is_some() && last_activity.elapsed() > timeout
+    }
+
+    /// Сохраняет состояние сетевого потока из сообщений шины
+    pub fn set_stream_status(&self, status: String) {
+        self.inner.borrow_mut().stream_status = Some(status);
+    }
+
+    /// Последнее известное состояние сетевого потока
+    pub fn stream_status(&self) -> Option<String> {
+        self.inner.borrow().stream_status.clone()
+    }
+
+    /// Статистика приёма, если источник - RTP поток
+    pub fn rtp_stats(&self) -> Option<RtpStats> {
+        let inner = self.inner.borrow();
+        let jitterbuffer = inner.bin.as_ref()?.by_name(JITTERBUFFER_NAME)?;
+        RtpStats::from_jitterbuffer(&jitterbuffer)
+    }
+
+    fn is_our_device(&self, device: &gstreamer::Device) -> bool {
+        match &self.inner.borrow().camera.source {
+            VideoSource::V4l2 { device: path, .. } => {
+                device_info(device).path.as_deref() == Some(path.as_str())
+            }
+            _ => false,
+        }
+    }
+
+    fn set_state(&self, state: SignalState) {
+        let callback = {
+            let mut inner = self.inner.borrow_mut();
+            if inner.state == state {
+                return;
+            }
+
+            let pad = match (state, &inner.source_pad) {
+                (SignalState::Live, Some(pad)) => pad.clone(),
+                _ => inner.fallback_pad.clone(),
+            };
+            inner.selector.set_property("active-pad", &pad);
+
+            inner.state = state;
+            inner.on_state.clone()
+        };
+
+        println!("Состояние сигнала: {:?}", state);
+        if let Some(callback) = callback {
+            callback(state);
+        }
+    }
+
+    fn attach(&self) -> Result<(), Box<dyn Error>> {
+        let mut inner = self.inner.borrow_mut();
+        let camera = &inner.camera;
+        let description =
+            camera
+                .source
+                .pipeline_segment(camera.width, camera.height, camera.framerate());
+
+        println!("Подключаем источник: {}", description);
+        let bin = gstreamer::parse::bin_from_description(&description, true)?;
+        camera.source.configure_bin(&bin);
+        inner.pipeline.add(&bin)?;
+        // Запоминаем bin сразу, чтобы detach() убрал его при ошибке ниже
+        inner.bin = Some(bin.clone());
+
+        let src_pad = bin.static_pad("src").ok_or("У источника нет src pad")?;
+        let sink_pad = inner
+            .selector
+            .request_pad_simple("sink_%u")
+            .ok_or("Не удалось получить вход input-selector")?;
+        inner.source_pad = Some(sink_pad.clone());
+        src_pad.link(&sink_pad)?;
+
+        // Сжатые кадры выводятся из bin через ghost pad в постоянный tee
+        if let Some(jpeg_tap) = bin.by_name(JPEG_TAP_NAME) {
+            let tap_pad = jpeg_tap
+                .request_pad_simple("src_%u")
+                .ok_or("Не удалось получить pad от jpeg tee")?;
+            let ghost = GhostPad::with_target(&tap_pad)?;
+            bin.add_pad(&ghost)?;
+            let jpeg_sink = inner
+                .jpeg_tee
+                .static_pad("sink")
+                .ok_or("У jpeg tee нет sink pad")?;
+            ghost.link(&jpeg_sink)?;
+        }
+
+        let last_buffer = inner.last_buffer.clone();
+        src_pad.add_probe(PadProbeType::BUFFER, move |_, _| {
+            *last_buffer.lock().unwrap() = Some(Instant::now());
+            PadProbeReturn::Ok
+        });
+
+        bin.sync_state_with_parent()?;
+        inner.attached_at = Some(Instant::now());
+        Ok(())
+    }
+
+    fn detach(&self) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(bin) = inner.bin.take() {
+            println!("Отключаем источник");
+            let _ = bin.set_state(State::Null);
+            if let Some(src_pad) = bin.static_pad("src")
+                && let Some(peer) = src_pad.peer()
+            {
+                let _ = src_pad.unlink(&peer);
+            }
+            if let Some(jpeg_sink) = inner.jpeg_tee.static_pad("sink")
+                && let Some(peer) = jpeg_sink.peer()
+            {
+                let _ = peer.unlink(&jpeg_sink);
+            }
+            let _ = inner.pipeline.remove(&bin);
+        }
+        if let Some(pad) = inner.source_pad.take() {
+            // Селектор не должен остаться переключённым на удаляемый вход
+            inner
+                .selector
+                .set_property("active-pad", &inner.fallback_pad);
+            inner.selector.release_request_pad(&pad);
+        }
+        *inner.last_buffer.lock().unwrap() = None;
+        inner.attached_at = None;
+    }
+
+    fn reconnect(&self) {
+        let missing_device = match &self.inner.borrow().camera.source {
+            VideoSource::V4l2 { device, .. } => !Path::new(device).exists(),
+            _ => false,
+        };
+
+        if missing_device {
+            self.schedule_retry();
+            return;
+        }
+
+        if let Err(e) = self.attach() {
+            println!("Переподключение не удалось: {}", e);
+            self.detach();
+            self.schedule_retry();
+        }
+    }
+
+    fn schedule_retry(&self) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.retry.is_some() {
+            return;
+        }
+
+        let delay = backoff(inner.attempt);
+        inner.attempt += 1;
+        println!("Повторное подключение источника через {:?}", delay);
+
+        let weak = Rc::downgrade(&self.inner);
+        inner.retry = Some(glib::timeout_add_local_once(delay, move || {
+            if let Some(manager) = SourceManager::from_weak(&weak) {
+                manager.inner.borrow_mut().retry = None;
+                manager.reconnect();
+            }
+        }));
+    }
+
+    fn cancel_retry(&self) {
+        if let Some(id) = self.inner.borrow_mut().retry.take() {
+            id.remove();
+        }
+    }
+}
+
+/// 1, 2, 4, ... секунд, но не больше MAX_BACKOFF
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.min(5)).min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gst_utils::with_default_context;
+    use gstreamer::{ClockTime, MessageType, ResourceError};
+
+    /// Тот же вход, что в main.rs: заставка на sink_0 селектора
+    fn test_pipeline() -> Pipeline {
+        gstreamer::init().unwrap();
+        gstreamer::parse::launch(
+            "input-selector name=sel sync-streams=false ! fakesink sync=false \
+            tee name=jt allow-not-linked=true \
+            videotestsrc is-live=true pattern=black ! video/x-raw,width=320,height=240 ! sel.",
+        )
+        .unwrap()
+        .dynamic_cast::<Pipeline>()
+        .unwrap()
+    }
+
+    fn test_camera() -> CameraConfig {
+        CameraConfig {
+            width: 320,
+            height: 240,
+            no_signal_timeout_ms: 500,
+            source: VideoSource::TestSrc {
+                pattern: String::from("ball"),
+            },
+            ..CameraConfig::default()
+        }
+    }
+
+    /// Крутит главный цикл, пока не выполнится условие или не выйдет время
+    fn wait_for(
+        context: &glib::MainContext,
+        manager: &SourceManager,
+        timeout: Duration,
+        condition: impl Fn(&SourceManager) -> bool,
+    ) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            while context.iteration(false) {}
+            manager.poll();
+            if condition(manager) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    fn signal(manager: &SourceManager) -> SignalState {
+        manager.inner.borrow().state
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_limit() {
+        assert_eq!(backoff(0), Duration::from_secs(1));
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(3), Duration::from_secs(8));
+        assert_eq!(backoff(10), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn source_error_switches_to_fallback_and_reattaches() {
+        with_default_context(source_error_test);
+    }
+
+    fn source_error_test(context: &glib::MainContext) {
+        let pipeline = test_pipeline();
+        let selector = pipeline.by_name("sel").unwrap();
+        let jpeg_tee = pipeline.by_name("jt").unwrap();
+        let manager = SourceManager::new(&pipeline, &selector, &jpeg_tee, &test_camera());
+        pipeline.set_state(State::Playing).unwrap();
+        manager.start();
+
+        assert!(wait_for(context, &manager, Duration::from_secs(5), |m| {
+            signal(m) == SignalState::Live
+        }));
+
+        // Ошибка от элемента внутри bin источника, как при отключении камеры
+        let source = manager
+            .inner
+            .borrow()
+            .bin
+            .as_ref()
+            .unwrap()
+            .iterate_sources()
+            .next()
+            .unwrap()
+            .unwrap();
+        gstreamer::element_error!(source, ResourceError::Read, ["имитация ошибки"]);
+
+        let bus = pipeline.bus().unwrap();
+        let msg = bus
+            .timed_pop_filtered(ClockTime::from_seconds(5), &[MessageType::Error])
+            .unwrap();
+        assert!(manager.owns(msg.src().unwrap()));
+        manager.handle_source_error();
+
+        assert_eq!(signal(&manager), SignalState::NoSignal);
+        assert!(manager.inner.borrow().bin.is_none());
+        assert!(manager.inner.borrow().retry.is_some());
+        assert_eq!(
+            selector.property::<Pad>("active-pad"),
+            manager.inner.borrow().fallback_pad
+        );
+
+        // Первая повторная попытка через секунду подключает новый bin
+        assert!(wait_for(context, &manager, Duration::from_secs(5), |m| {
+            signal(m) == SignalState::Live
+        }));
+        assert!(manager.inner.borrow().retry.is_none());
+        assert_eq!(manager.inner.borrow().attempt, 0);
+
+        pipeline.set_state(State::Null).unwrap();
+    }
+}
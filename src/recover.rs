@@ -0,0 +1,269 @@
+use crate::profile::Container;
+use crate::session::{MANIFEST_NAME, Session};
+use gstreamer::prelude::*;
+use gstreamer::{ClockTime, MessageType, MessageView, Pipeline, State};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Суффикс файла-метки, который лежит рядом с записью, пока она не
+/// завершена. Если метка осталась, запись оборвалась.
+const UNFINISHED_SUFFIX: &str = ".recording";
+
+/// Сколько ждать пересборки одного файла
+const REMUX_TIMEOUT: ClockTime = ClockTime::from_seconds(600);
+
+/// Путь к метке незавершённой записи
+pub fn unfinished_marker(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(UNFINISHED_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Ищет в каталоге оборванные записи и пересобирает их. Запускать, когда
+/// приложение не пишет в этот каталог: идущая запись тоже выглядит
+/// незавершённой.
+pub fn run(dir: &Path, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    println!("Ищем незавершённые записи в {}", dir.display());
+
+    let mut found = 0;
+    let mut recovered = 0;
+    scan(dir, dry_run, &mut found, &mut recovered)?;
+
+    println!(
+        "Найдено незавершённых записей: {}, восстановлено: {}",
+        found, recovered
+    );
+    Ok(())
+}
+
+/// Проходит каталог и подкаталоги (например, каталоги по дням)
+fn scan(
+    dir: &Path,
+    dry_run: bool,
+    found: &mut usize,
+    recovered: &mut usize,
+) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(dir)?.filter_map(Result::ok) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        // Отдельный файл: рядом осталась метка
+        if let Some(recording_name) = name.strip_suffix(UNFINISHED_SUFFIX) {
+            let recording = path.with_file_name(recording_name);
+            if !recording.is_file() {
+                println!("Метка без записи, удаляем: {}", path.display());
+                if !dry_run {
+                    let _ = fs::remove_file(&path);
+                }
+                continue;
+            }
+
+            *found += 1;
+            println!("Незавершённая запись: {}", recording.display());
+            if dry_run {
+                continue;
+            }
+            match recover_file(&recording) {
+                Ok(()) => {
+                    let _ = fs::remove_file(&path);
+                    *recovered += 1;
+                }
+                Err(e) => println!("Не удалось восстановить {}: {}", recording.display(), e),
+            }
+            continue;
+        }
+
+        // Сессия сегментов: манифест без отметки о завершении
+        if path.is_dir() && path.join(MANIFEST_NAME).is_file() {
+            let mut session = match Session::open(&path) {
+                Ok(session) => session,
+                Err(e) => {
+                    println!("Не удалось прочитать сессию {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if session.is_finished() {
+                continue;
+            }
+
+            *found += 1;
+            println!("Незавершённая сессия: {}", path.display());
+            if dry_run {
+                continue;
+            }
+            let result = match session.last_segment() {
+                Some(segment) => recover_file(&segment),
+                None => Ok(()),
+            };
+            match result {
+                Ok(()) => {
+                    session.finish(None, true);
+                    *recovered += 1;
+                }
+                Err(e) => println!("Не удалось восстановить {}: {}", path.display(), e),
+            }
+            continue;
+        }
+
+        if path.is_dir()
+            && let Err(e) = scan(&path, dry_run, found, recovered)
+        {
+            println!("Не удалось просмотреть {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Пересобирает файл во временный и заменяет им исходный
+fn recover_file(path: &Path) -> Result<(), Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .ok_or("у файла нет расширения")?;
+    let container = Container::from_extension(extension)
+        .ok_or_else(|| format!("неизвестный контейнер '{}'", extension))?;
+
+    let output = path.with_extension(format!("recovered.{}", extension));
+    if let Err(e) = remux(path, &output, container) {
+        let _ = fs::remove_file(&output);
+        return Err(e);
+    }
+
+    fs::rename(&output, path)?;
+    println!("Восстановлен: {}", path.display());
+    Ok(())
+}
+
+/// Демультиплексор читает всё, что успело записаться, а muxer заново
+/// пишет индекс и длительность. Обрезанный конец файла не считается
+/// ошибкой: muxer завершается по уже прочитанным данным.
+fn remux(input: &Path, output: &Path, container: Container) -> Result<(), Box<dyn Error>> {
+    let description = format!(
+        "filesrc location=\"{}\" ! parsebin name=parse {} name=mux ! filesink location=\"{}\"",
+        input.display(),
+        container.muxer_element(),
+        output.display()
+    );
+    println!("Пересобираем: {}", description);
+
+    let pipeline = gstreamer::parse::launch(&description)?
+        .dynamic_cast::<Pipeline>()
+        .map_err(|_| "не удалось создать pipeline")?;
+    let parse = pipeline.by_name("parse").ok_or("нет parsebin")?;
+    let mux = pipeline.by_name("mux").ok_or("нет muxer")?;
+
+    parse.connect_pad_added({
+        let pipeline = pipeline.downgrade();
+        let mux = mux.clone();
+        move |_, pad| {
+            if let Some(sink_pad) = mux.compatible_pad(pad, None) {
+                if let Err(e) = pad.link(&sink_pad) {
+                    println!("Не удалось подключить поток {}: {}", pad.name(), e);
+                }
+                return;
+            }
+
+            // Поток, который контейнер не хранит, просто выбрасываем
+            println!(
+                "Поток {} не поддерживается контейнером, пропускаем",
+                pad.name()
+            );
+            let Some(pipeline) = pipeline.upgrade() else {
+                return;
+            };
+            if let Ok(fakesink) = gstreamer::ElementFactory::make("fakesink").build() {
+                let _ = pipeline.add(&fakesink);
+                let _ = fakesink.sync_state_with_parent();
+                if let Some(sink_pad) = fakesink.static_pad("sink") {
+                    let _ = pad.link(&sink_pad);
+                }
+            }
+        }
+    });
+
+    pipeline.set_state(State::Playing)?;
+
+    let bus = pipeline.bus().ok_or("нет шины pipeline")?;
+    let mut eos_forced = false;
+    let result: Result<(), Box<dyn Error>> = loop {
+        let Some(msg) =
+            bus.timed_pop_filtered(REMUX_TIMEOUT, &[MessageType::Eos, MessageType::Error])
+        else {
+            break Err("пересборка не завершилась вовремя".into());
+        };
+
+        match msg.view() {
+            MessageView::Eos(_) => break Ok(()),
+            MessageView::Error(err) if !eos_forced => {
+                println!("Файл читается не до конца: {}", err.error());
+                let sink_pads = mux.sink_pads();
+                if sink_pads.is_empty() {
+                    break Err(format!("в файле не найдено потоков: {}", err.error()).into());
+                }
+                eos_forced = true;
+                for pad in sink_pads {
+                    pad.send_event(gstreamer::event::Eos::new());
+                }
+                // Выброшенным потокам тоже нужен EOS, иначе pipeline не завершится
+                for sink in pipeline.iterate_sinks().into_iter().filter_map(Result::ok) {
+                    if sink.factory().is_some_and(|f| f.name() == "fakesink")
+                        && let Some(pad) = sink.static_pad("sink")
+                    {
+                        pad.send_event(gstreamer::event::Eos::new());
+                    }
+                }
+            }
+            MessageView::Error(err) => break Err(err.error().to_string().into()),
+            _ => {}
+        }
+    };
+
+    pipeline.set_state(State::Null)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SegmentConfig;
+
+    /// Каталог записей во временном каталоге, удаляется после теста
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("ncy_recover_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn session(&self, name: &str, finished: bool) {
+            let mut session =
+                Session::create(&self.0.join(name), "mkv", "mkv", &SegmentConfig::default())
+                    .unwrap();
+            session.finish(None, finished);
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn aborted_session_is_unfinished() {
+        let dir = TestDir::new("sessions");
+        dir.session("clean", true);
+        dir.session("aborted", false);
+
+        let (mut found, mut recovered) = (0, 0);
+        scan(&dir.0, true, &mut found, &mut recovered).unwrap();
+        assert_eq!((found, recovered), (1, 0));
+        assert!(!Session::open(&dir.0.join("aborted")).unwrap().is_finished());
+        assert!(Session::open(&dir.0.join("clean")).unwrap().is_finished());
+    }
+}
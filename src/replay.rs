@@ -0,0 +1,177 @@
+use gstreamer::prelude::*;
+use gstreamer::{
+    Buffer, Caps, ClockTime, Element, MessageView, PadProbeData, PadProbeReturn, PadProbeType,
+    Pipeline, State,
+};
+use gtk4::gdk;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+/// Имя fakesink ветки повтора в pipeline
+const REPLAY_SINK_NAME: &str = "replay";
+
+/// Ветка от tee: кадры сжимаются в JPEG, чтобы несколько секунд видео
+/// занимали немного памяти и воспроизводились с любого кадра. Очередь
+/// сбрасывает кадры, если кодировщик не успевает, живое видео не ждёт.
+pub fn pipeline_segment() -> String {
+    format!(
+        "t. ! queue leaky=downstream max-size-buffers=5 ! jpegenc quality=80 ! \
+        fakesink name={} sync=false async=false",
+        REPLAY_SINK_NAME
+    )
+}
+
+#[derive(Default)]
+struct Frames {
+    caps: Option<Caps>,
+    buffers: VecDeque<Buffer>,
+}
+
+/// Последние секунды живого видео в памяти
+#[derive(Clone)]
+pub struct ReplayBuffer {
+    frames: Arc<Mutex<Frames>>,
+}
+
+impl ReplayBuffer {
+    /// Начинает копить кадры ветки из `pipeline_segment`
+    pub fn new(pipeline: &Pipeline, seconds: u32) -> Option<Self> {
+        let sink = pipeline.by_name(REPLAY_SINK_NAME)?;
+        let sink_pad = sink.static_pad("sink")?;
+        let length = ClockTime::from_seconds(seconds as u64);
+        let frames = Arc::new(Mutex::new(Frames::default()));
+
+        sink_pad.add_probe(PadProbeType::BUFFER, {
+            let frames = frames.clone();
+            move |pad, info| {
+                let Some(PadProbeData::Buffer(buffer)) = &info.data else {
+                    return PadProbeReturn::Ok;
+                };
+                let mut frames = frames.lock().unwrap();
+
+                // Старые кадры с другими caps уже не показать тем же декодером
+                let caps = pad.current_caps();
+                if caps != frames.caps {
+                    frames.buffers.clear();
+                    frames.caps = caps;
+                }
+
+                // После перезапуска pipeline время начинается заново, старые
+                // кадры уже не выстроить в один ряд с новыми
+                let newest = buffer.pts().unwrap_or(ClockTime::ZERO);
+                if frames
+                    .buffers
+                    .back()
+                    .and_then(|b| b.pts())
+                    .is_some_and(|last| newest < last)
+                {
+                    frames.buffers.clear();
+                }
+                frames.buffers.push_back(buffer.clone());
+
+                while let Some(oldest) = frames.buffers.front().and_then(|b| b.pts()) {
+                    if newest.saturating_sub(oldest) <= length {
+                        break;
+                    }
+                    frames.buffers.pop_front();
+                }
+                PadProbeReturn::Ok
+            }
+        });
+
+        Some(Self { frames })
+    }
+
+    /// Копия буфера: caps и кадры от старых к новым
+    fn frames(&self) -> Option<(Caps, Vec<Buffer>)> {
+        let frames = self.frames.lock().unwrap();
+        let caps = frames.caps.clone()?;
+        if frames.buffers.is_empty() {
+            return None;
+        }
+        Some((caps, frames.buffers.iter().cloned().collect()))
+    }
+}
+
+/// Отдельный pipeline, который один раз показывает содержимое буфера
+/// повтора. Живой pipeline и запись при этом не затрагиваются.
+pub struct ReplayPlayer {
+    pipeline: Pipeline,
+    sink: Element,
+}
+
+impl ReplayPlayer {
+    pub fn start(buffer: &ReplayBuffer) -> Result<Self, Box<dyn Error>> {
+        let (caps, buffers) = buffer.frames().ok_or("буфер повтора пуст")?;
+
+        let pipeline = gstreamer::parse::launch(
+            "appsrc name=src format=time max-bytes=0 ! jpegdec ! videoconvert ! \
+            gtk4paintablesink name=replaysink",
+        )?
+        .dynamic_cast::<Pipeline>()
+        .map_err(|_| "не удалось создать pipeline")?;
+        let src = pipeline.by_name("src").ok_or("нет appsrc")?;
+        let sink = pipeline
+            .by_name("replaysink")
+            .ok_or("нет gtk4paintablesink")?;
+        src.set_property("caps", &caps);
+        // Кадры подаются после запуска, остановленный appsrc их не принимает.
+        // При ошибке ниже Drop останавливает pipeline.
+        let player = Self { pipeline, sink };
+        player.pipeline.set_state(State::Playing)?;
+
+        // Время кадров отсчитывается от первого кадра в буфере
+        let first = buffers
+            .first()
+            .and_then(|b| b.pts())
+            .unwrap_or(ClockTime::ZERO);
+        for buffer in &buffers {
+            let pts = buffer.pts().map(|pts| pts.saturating_sub(first));
+            let mut buffer = buffer.copy();
+            if let Some(buffer) = buffer.get_mut() {
+                buffer.set_pts(pts);
+                buffer.set_dts(ClockTime::NONE);
+            }
+            let result = src.emit_by_name::<gstreamer::FlowReturn>("push-buffer", &[&buffer]);
+            if result != gstreamer::FlowReturn::Ok {
+                return Err(format!("appsrc не принял кадр: {:?}", result).into());
+            }
+        }
+        let result = src.emit_by_name::<gstreamer::FlowReturn>("end-of-stream", &[]);
+        if result != gstreamer::FlowReturn::Ok {
+            return Err(format!("appsrc не принял конец потока: {:?}", result).into());
+        }
+
+        println!("Повтор: {} кадров", buffers.len());
+        Ok(player)
+    }
+
+    pub fn paintable(&self) -> gdk::Paintable {
+        self.sink.property::<gdk::Paintable>("paintable")
+    }
+
+    /// Повтор доиграл до конца или остановился с ошибкой
+    pub fn is_finished(&self) -> bool {
+        let Some(bus) = self.pipeline.bus() else {
+            return true;
+        };
+        while let Some(msg) = bus.pop() {
+            match msg.view() {
+                MessageView::Eos(_) => return true,
+                MessageView::Error(err) => {
+                    println!("Ошибка повтора: {}", err.error());
+                    return true;
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
+impl Drop for ReplayPlayer {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::replay::{ReplayBuffer, ReplayPlayer};
+use gtk4::prelude::*;
+use gtk4::{Align, Box as GtkBox, Button, Label, Orientation, Picture, glib};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Окно повтора поверх живого видео: маленькое в углу или во весь экран
+pub struct ReplayView {
+    root: GtkBox,
+    picture: Picture,
+    size_button: Button,
+    buffer: ReplayBuffer,
+    player: RefCell<Option<ReplayPlayer>>,
+    timer: RefCell<Option<glib::SourceId>>,
+    fullscreen: Cell<bool>,
+}
+
+impl ReplayView {
+    pub fn new(buffer: ReplayBuffer) -> Rc<Self> {
+        let root = GtkBox::new(Orientation::Vertical, 2);
+        root.add_css_class("replay");
+        root.set_visible(false);
+
+        let picture = Picture::new();
+        picture.set_vexpand(true);
+        root.append(&picture);
+
+        let controls = GtkBox::new(Orientation::Horizontal, 5);
+        let title = Label::new(Some("Повтор"));
+        title.set_hexpand(true);
+        title.set_xalign(0.0);
+        let size_button = Button::new();
+        let close_button = Button::with_label("Закрыть");
+        controls.append(&title);
+        controls.append(&size_button);
+        controls.append(&close_button);
+        root.append(&controls);
+
+        let view = Rc::new(Self {
+            root,
+            picture,
+            size_button,
+            buffer,
+            player: RefCell::new(None),
+            timer: RefCell::new(None),
+            fullscreen: Cell::new(false),
+        });
+        view.set_fullscreen(false);
+
+        view.size_button.connect_clicked({
+            let view = Rc::downgrade(&view);
+            move |_| {
+                if let Some(view) = view.upgrade() {
+                    view.set_fullscreen(!view.fullscreen.get());
+                }
+            }
+        });
+        close_button.connect_clicked({
+            let view = Rc::downgrade(&view);
+            move |_| {
+                if let Some(view) = view.upgrade() {
+                    view.stop();
+                }
+            }
+        });
+
+        view
+    }
+
+    /// Виджет для `Overlay` над окном видео
+    pub fn widget(&self) -> &GtkBox {
+        &self.root
+    }
+
+    /// Показывает последние секунды с начала. Повторное нажатие
+    /// начинает повтор заново с новым содержимым буфера.
+    pub fn start(self: &Rc<Self>) {
+        self.stop();
+
+        let player = match ReplayPlayer::start(&self.buffer) {
+            Ok(player) => player,
+            Err(e) => {
+                println!("Не удалось начать повтор: {}", e);
+                return;
+            }
+        };
+        self.picture.set_paintable(Some(&player.paintable()));
+        *self.player.borrow_mut() = Some(player);
+        self.root.set_visible(true);
+
+        let view = Rc::downgrade(self);
+        let timer = glib::timeout_add_local(Duration::from_millis(200), move || {
+            let Some(view) = view.upgrade() else {
+                return glib::ControlFlow::Break;
+            };
+            let finished = view
+                .player
+                .borrow()
+                .as_ref()
+                .is_none_or(ReplayPlayer::is_finished);
+            if finished {
+                // Таймер удаляется возвратом Break, а не в stop()
+                view.timer.borrow_mut().take();
+                view.stop();
+                return glib::ControlFlow::Break;
+            }
+            glib::ControlFlow::Continue
+        });
+        *self.timer.borrow_mut() = Some(timer);
+    }
+
+    pub fn stop(&self) {
+        if let Some(timer) = self.timer.borrow_mut().take() {
+            timer.remove();
+        }
+        self.player.borrow_mut().take();
+        self.picture.set_paintable(None::<&gtk4::gdk::Paintable>);
+        self.root.set_visible(false);
+    }
+
+    fn set_fullscreen(&self, fullscreen: bool) {
+        self.fullscreen.set(fullscreen);
+        if fullscreen {
+            self.root.set_halign(Align::Fill);
+            self.root.set_valign(Align::Fill);
+            self.root.set_size_request(-1, -1);
+            self.root.remove_css_class("replay-pip");
+            self.size_button.set_label("Уменьшить");
+        } else {
+            self.root.set_halign(Align::End);
+            self.root.set_valign(Align::Start);
+            self.root.set_size_request(320, 220);
+            self.root.add_css_class("replay-pip");
+            self.size_button.set_label("Во весь экран");
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use gstreamer::Element;
+use gstreamer::prelude::*;
+
+/// Статистика rtpjitterbuffer для отображения в интерфейсе
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RtpStats {
+    /// Пакетов передано дальше
+    pub pushed: u64,
+    /// Потерянных пакетов
+    pub lost: u64,
+    /// Пакетов, пришедших слишком поздно
+    pub late: u64,
+    /// Средний джиттер, мс
+    pub jitter_ms: f64,
+}
+
+impl RtpStats {
+    /// Читает свойство `stats` у rtpjitterbuffer
+    pub fn from_jitterbuffer(jitterbuffer: &Element) -> Option<Self> {
+        let stats = jitterbuffer.property::<gstreamer::Structure>("stats");
+
+        Some(Self {
+            pushed: stats.get::<u64>("num-pushed").ok()?,
+            lost: stats.get::<u64>("num-lost").ok()?,
+            late: stats.get::<u64>("num-late").unwrap_or(0),
+            jitter_ms: stats.get::<u64>("avg-jitter").unwrap_or(0) as f64 / 1_000_000.0,
+        })
+    }
+
+    /// Доля потерянных пакетов, в процентах
+    pub fn loss_percent(&self) -> f64 {
+        let total = self.pushed + self.lost;
+        if total == 0 {
+            return 0.0;
+        }
+        self.lost as f64 * 100.0 / total as f64
+    }
+
+    /// Строка для метки в интерфейсе
+    pub fn summary(&self) -> String {
+        format!(
+            "RTP: потери {} ({:.1}%)\nопоздали {}\nджиттер {:.1} мс",
+            self.lost,
+            self.loss_percent(),
+            self.late,
+            self.jitter_ms
+        )
+    }
+}
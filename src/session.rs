@@ -0,0 +1,264 @@
+use crate::config::SegmentConfig;
+use gstreamer::ClockTime;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Имя файла со списком сегментов в каталоге сессии
+pub const MANIFEST_NAME: &str = "session.json";
+
+/// Префикс имён сегментов: session_0001.mp4, session_0002.mp4, ...
+const SEGMENT_PREFIX: &str = "session_";
+
+/// Один файл сегментированной записи
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Segment {
+    /// Имя файла внутри каталога сессии
+    pub file: String,
+    /// Начало сегмента от начала сессии, мс
+    pub start_ms: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Manifest {
+    started_at: String,
+    profile: String,
+    max_segment_seconds: u64,
+    max_segment_mb: u64,
+    /// Сессия завершена, все сегменты дописаны
+    finished: bool,
+    segments: Vec<Segment>,
+}
+
+/// Сессия сегментированной записи: каталог с файлами сегментов и
+/// манифестом. Манифест переписывается при каждом новом сегменте, так что
+/// после аварийного завершения в нём есть все начатые файлы.
+pub struct Session {
+    dir: PathBuf,
+    extension: String,
+    /// running-time первого сегмента, от него считается start_ms
+    first_running_time: Option<u64>,
+    manifest: Manifest,
+}
+
+impl Session {
+    pub fn create(
+        dir: &Path,
+        extension: &str,
+        profile: &str,
+        segments: &SegmentConfig,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let session = Self {
+            dir: dir.to_path_buf(),
+            extension: extension.to_string(),
+            first_running_time: None,
+            manifest: Manifest {
+                started_at: chrono::Local::now().to_rfc3339(),
+                profile: profile.to_string(),
+                max_segment_seconds: segments.max_seconds,
+                max_segment_mb: segments.max_mb,
+                finished: false,
+                segments: Vec::new(),
+            },
+        };
+        session.write_manifest()?;
+        Ok(session)
+    }
+
+    /// Открывает существующую сессию по её манифесту
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(dir.join(MANIFEST_NAME))?;
+        let manifest: Manifest = serde_json::from_str(&text).map_err(io::Error::other)?;
+
+        // Расширение - по сегментам из манифеста или по файлам в каталоге
+        let names = manifest.segments.iter().map(|s| s.file.clone()).chain(
+            fs::read_dir(dir)?
+                .filter_map(Result::ok)
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| name.starts_with(SEGMENT_PREFIX)),
+        );
+        let extension = names
+            .filter_map(|name| Some(Path::new(&name).extension()?.to_string_lossy().into_owned()))
+            .next()
+            .unwrap_or_default();
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            extension,
+            first_running_time: None,
+            manifest,
+        })
+    }
+
+    /// Запись сессии была корректно завершена
+    pub fn is_finished(&self) -> bool {
+        self.manifest.finished
+    }
+
+    /// Последний по номеру сегмент на диске: при обрыве записи
+    /// незавершённым может остаться только он
+    pub fn last_segment(&self) -> Option<PathBuf> {
+        let prefix_len = SEGMENT_PREFIX.len();
+        fs::read_dir(&self.dir)
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(SEGMENT_PREFIX))
+            .max_by_key(|name| {
+                name[prefix_len..]
+                    .split('.')
+                    .next()
+                    .and_then(|n| n.parse::<u32>().ok())
+                    .unwrap_or(0)
+            })
+            .map(|name| self.dir.join(name))
+    }
+
+    /// Шаблон имён всех сегментов для splitmuxsrc
+    pub fn segment_glob(&self) -> String {
+        self.dir
+            .join(format!("{}*.{}", SEGMENT_PREFIX, self.extension))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Файлы сегментов по порядку
+    pub fn segment_paths(&self) -> Vec<PathBuf> {
+        self.manifest
+            .segments
+            .iter()
+            .map(|segment| self.dir.join(&segment.file))
+            .collect()
+    }
+
+    /// Длительность по манифесту, если известна для всех сегментов
+    pub fn duration_ms(&self) -> Option<u64> {
+        self.manifest.segments.iter().map(|s| s.duration_ms).sum()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Шаблон имён для свойства `location` у splitmuxsink
+    pub fn location_pattern(&self) -> String {
+        self.dir
+            .join(format!("{}%04d.{}", SEGMENT_PREFIX, self.extension))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// splitmuxsink открыл новый файл
+    pub fn fragment_opened(&mut self, location: &str, running_time: u64) {
+        let first = *self.first_running_time.get_or_insert(running_time);
+        let file = file_name(location);
+        println!("Новый сегмент записи: {}", file);
+
+        if !self.manifest.segments.iter().any(|s| s.file == file) {
+            self.manifest.segments.push(Segment {
+                file,
+                start_ms: Some(running_time.saturating_sub(first) / 1_000_000),
+                duration_ms: None,
+                size: None,
+            });
+        }
+        self.save();
+    }
+
+    /// splitmuxsink дописал файл
+    pub fn fragment_closed(&mut self, location: &str, running_time: u64) {
+        let first = self.first_running_time.unwrap_or(running_time);
+        let file = file_name(location);
+
+        if let Some(segment) = self.manifest.segments.iter_mut().find(|s| s.file == file) {
+            let end_ms = running_time.saturating_sub(first) / 1_000_000;
+            segment.duration_ms = segment.start_ms.map(|start| end_ms.saturating_sub(start));
+            segment.size = fs::metadata(self.dir.join(&segment.file))
+                .map(|m| m.len())
+                .ok();
+        }
+        self.save();
+    }
+
+    /// Завершает сессию: уточняет размеры по файлам на диске, добавляет
+    /// сегменты, о которых не успели прийти сообщения, и возвращает общий
+    /// размер записи. `end_running_time` - конец записи, по нему считается
+    /// длина сегмента, если сообщение о его закрытии ещё не пришло.
+    /// Завершённой сессия отмечается только при `finished`, иначе её
+    /// потом найдёт `recover`.
+    pub fn finish(&mut self, end_running_time: Option<ClockTime>, finished: bool) -> u64 {
+        let mut on_disk: Vec<String> = fs::read_dir(&self.dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|e| e.file_name().to_string_lossy().into_owned())
+                    .filter(|name| {
+                        name.starts_with(SEGMENT_PREFIX)
+                            && name.ends_with(&format!(".{}", self.extension))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        on_disk.sort();
+
+        for file in on_disk {
+            if !self.manifest.segments.iter().any(|s| s.file == file) {
+                self.manifest.segments.push(Segment {
+                    file,
+                    start_ms: None,
+                    duration_ms: None,
+                    size: None,
+                });
+            }
+        }
+
+        if let (Some(end), Some(first), Some(last)) = (
+            end_running_time,
+            self.first_running_time,
+            self.manifest.segments.last_mut(),
+        ) && let (None, Some(start)) = (last.duration_ms, last.start_ms)
+        {
+            let end_ms = end.nseconds().saturating_sub(first) / 1_000_000;
+            last.duration_ms = Some(end_ms.saturating_sub(start));
+        }
+
+        let mut total = 0;
+        for segment in &mut self.manifest.segments {
+            segment.size = fs::metadata(self.dir.join(&segment.file))
+                .map(|m| m.len())
+                .ok();
+            total += segment.size.unwrap_or(0);
+        }
+
+        self.manifest.finished = finished;
+        self.save();
+        total
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.write_manifest() {
+            println!("Не удалось записать манифест сессии: {}", e);
+        }
+    }
+
+    /// Пишет манифест через временный файл, чтобы он не оказался обрезанным
+    fn write_manifest(&self) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.manifest).map_err(io::Error::other)?;
+        let path = self.dir.join(MANIFEST_NAME);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, path)
+    }
+}
+
+fn file_name(location: &str) -> String {
+    Path::new(location)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| location.to_string())
+}
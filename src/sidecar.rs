@@ -0,0 +1,191 @@
+use crate::config::CameraConfig;
+use crate::profile::RecordingProfile;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Ошибка pipeline во время записи
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoggedError {
+    pub at: String,
+    pub source: String,
+    pub message: String,
+}
+
+/// Отметка оператора в записи
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub at: String,
+    /// Смещение от начала записи, мс
+    pub offset_ms: u64,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// Откуда вырезан фрагмент
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClipSource {
+    /// Исходная запись
+    pub source: PathBuf,
+    /// Границы фрагмента в исходной записи, мс
+    pub start_ms: u64,
+    pub end_ms: u64,
+    /// Фрагмент перекодирован с точностью до кадра
+    pub reencoded: bool,
+}
+
+/// Содержимое файла метаданных записи
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SidecarData {
+    pub started_at: String,
+    #[serde(default)]
+    pub stopped_at: Option<String>,
+    /// Запись завершена корректно
+    #[serde(default)]
+    pub finished: bool,
+    pub profile_name: String,
+    pub profile: RecordingProfile,
+    pub source_device: String,
+    pub camera: CameraConfig,
+    /// Кадров выброшено videorate с начала записи
+    #[serde(default)]
+    pub dropped_frames: u64,
+    #[serde(default)]
+    pub errors: Vec<LoggedError>,
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+    /// Есть только у фрагментов, вырезанных из других записей
+    #[serde(default)]
+    pub clip: Option<ClipSource>,
+}
+
+impl SidecarData {
+    pub fn new(camera: &CameraConfig, profile_name: &str, profile: &RecordingProfile) -> Self {
+        let mut camera = camera.clone();
+        camera.source = camera.source.without_secrets();
+
+        Self {
+            started_at: now(),
+            stopped_at: None,
+            finished: false,
+            profile_name: profile_name.to_string(),
+            profile: profile.clone(),
+            source_device: camera.source.device(),
+            camera,
+            dropped_frames: 0,
+            errors: Vec::new(),
+            bookmarks: Vec::new(),
+            clip: None,
+        }
+    }
+}
+
+/// JSON файл рядом с записью. Переписывается целиком при каждом изменении,
+/// так что после сбоя в нём остаётся всё, что было до него.
+pub struct Sidecar {
+    path: PathBuf,
+    data: SidecarData,
+}
+
+impl Sidecar {
+    pub fn create(
+        recording: &Path,
+        camera: &CameraConfig,
+        profile_name: &str,
+        profile: &RecordingProfile,
+    ) -> Self {
+        let sidecar = Self {
+            path: sidecar_path(recording),
+            data: SidecarData::new(camera, profile_name, profile),
+        };
+        sidecar.save();
+        sidecar
+    }
+
+    pub fn set_dropped_frames(&mut self, dropped: u64) {
+        if self.data.dropped_frames != dropped {
+            self.data.dropped_frames = dropped;
+            self.save();
+        }
+    }
+
+    pub fn add_error(&mut self, source: &str, message: &str) {
+        self.data.errors.push(LoggedError {
+            at: now(),
+            source: source.to_string(),
+            message: message.to_string(),
+        });
+        self.save();
+    }
+
+    pub fn add_bookmark(&mut self, offset_ms: u64, label: Option<&str>) {
+        self.data.bookmarks.push(Bookmark {
+            at: now(),
+            offset_ms,
+            label: label.map(str::to_string),
+        });
+        self.save();
+    }
+
+    /// Запись остановлена, `finished` - файл завершён корректно
+    pub fn finish(&mut self, finished: bool) {
+        self.data.stopped_at = Some(now());
+        self.data.finished = finished;
+        self.save();
+    }
+
+    /// Метаданные фрагмента: сведения исходной записи `source`, её метки
+    /// внутри фрагмента со смещением от его начала и ссылка на источник
+    pub fn create_clip(clip_path: &Path, source: SidecarData, clip: ClipSource) -> Self {
+        let bookmarks = source
+            .bookmarks
+            .into_iter()
+            .filter(|b| (clip.start_ms..=clip.end_ms).contains(&b.offset_ms))
+            .map(|b| Bookmark {
+                offset_ms: b.offset_ms - clip.start_ms,
+                ..b
+            })
+            .collect();
+
+        let mut sidecar = Self {
+            path: sidecar_path(clip_path),
+            data: SidecarData {
+                bookmarks,
+                errors: Vec::new(),
+                clip: Some(clip),
+                ..source
+            },
+        };
+        sidecar.finish(true);
+        sidecar
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.write() {
+            println!("Не удалось записать {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.data).map_err(io::Error::other)?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+/// Читает метаданные записи, если они есть
+pub fn load(recording: &Path) -> Option<SidecarData> {
+    let text = fs::read_to_string(sidecar_path(recording)).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// Файл метаданных записи: имя записи с расширением .json
+pub fn sidecar_path(recording: &Path) -> PathBuf {
+    recording.with_extension("json")
+}
+
+fn now() -> String {
+    chrono::Local::now().to_rfc3339()
+}
@@ -0,0 +1,91 @@
+use gstreamer::prelude::*;
+use gstreamer::{ClockTime, Element, PadProbeData, PadProbeReturn, PadProbeType};
+use gtk4::gio;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+/// Сколько ждать кадр и его кодирование
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Что подставляется в `{profile}` шаблона имени для снимков
+pub const PROFILE_NAME: &str = "snapshot";
+
+/// Формат снимка
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    Jpeg,
+    Png,
+}
+
+impl SnapshotFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SnapshotFormat::Jpeg => "jpg",
+            SnapshotFormat::Png => "png",
+        }
+    }
+
+    fn caps(&self) -> gstreamer::Caps {
+        match self {
+            SnapshotFormat::Jpeg => gstreamer::Caps::builder("image/jpeg").build(),
+            SnapshotFormat::Png => gstreamer::Caps::builder("image/png").build(),
+        }
+    }
+}
+
+/// Сохраняет в `path` следующий кадр, пришедший на вход `tee`. Кадр
+/// забирает проба на sink pad tee, поэтому ветки показа и записи ничего
+/// не замечают. Ожидание кадра и кодирование идут в фоновом потоке.
+pub async fn capture(
+    tee: &Element,
+    path: PathBuf,
+    format: SnapshotFormat,
+) -> Result<PathBuf, String> {
+    let sink_pad = tee.static_pad("sink").ok_or("У tee нет sink pad")?;
+
+    let (sender, receiver) = channel();
+    let sender = Mutex::new(Some(sender));
+    sink_pad.add_probe(PadProbeType::BUFFER, move |pad, info| {
+        let Some(PadProbeData::Buffer(ref buffer)) = info.data else {
+            return PadProbeReturn::Ok;
+        };
+        if let Some(sender) = sender.lock().unwrap().take() {
+            let mut sample = gstreamer::Sample::builder().buffer(buffer);
+            let caps = pad.current_caps();
+            if let Some(caps) = &caps {
+                sample = sample.caps(caps);
+            }
+            let _ = sender.send(sample.build());
+        }
+        PadProbeReturn::Remove
+    });
+
+    gio::spawn_blocking(move || -> Result<PathBuf, String> {
+        let sample = receiver
+            .recv_timeout(SNAPSHOT_TIMEOUT)
+            .map_err(|_| String::from("Нет кадров для снимка"))?;
+        let image = gstreamer_video::convert_sample(
+            &sample,
+            &format.caps(),
+            ClockTime::from_nseconds(SNAPSHOT_TIMEOUT.as_nanos() as u64),
+        )
+        .map_err(|e| format!("Не удалось закодировать снимок: {}", e))?;
+
+        let buffer = image.buffer().ok_or("Пустой снимок")?;
+        let map = buffer
+            .map_readable()
+            .map_err(|_| String::from("Не удалось прочитать снимок"))?;
+        fs::write(&path, map.as_slice())
+            .map_err(|e| format!("Не удалось сохранить {}: {}", path.display(), e))?;
+
+        println!("Снимок сохранён: {}", path.display());
+        Ok(path)
+    })
+    .await
+    .map_err(|_| String::from("Поток снимка завершился с ошибкой"))?
+}
@@ -0,0 +1,463 @@
+use crate::config::StorageConfig;
+use crate::recover::unfinished_marker;
+use crate::session::MANIFEST_NAME;
+use crate::sidecar::sidecar_path;
+use gtk4::gio;
+use gtk4::gio::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Расширения файлов записей, см. `Container::extension`
+const RECORDING_EXTENSIONS: [&str; 5] = ["mp4", "mkv", "ts", "webm", "avi"];
+
+/// Суффикс файла-метки рядом с записью, которую нельзя удалять
+const PROTECT_SUFFIX: &str = ".protected";
+
+const MB: u64 = 1024 * 1024;
+
+/// Источник сведений о свободном месте. Позволяет подменить файловую
+/// систему, например чтобы проверить удаление старых записей без
+/// заполнения диска.
+pub trait FsStats {
+    /// Свободное место на файловой системе с каталогом `path`, байты
+    fn free_space(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// Свободное место по данным GIO
+pub struct GioFsStats;
+
+impl FsStats for GioFsStats {
+    fn free_space(&self, path: &Path) -> io::Result<u64> {
+        let info = gio::File::for_path(path)
+            .query_filesystem_info("filesystem::free", gio::Cancellable::NONE)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(info.attribute_uint64("filesystem::free"))
+    }
+}
+
+/// Запись в каталоге: файл или каталог сессии с сегментами
+#[derive(Clone, Debug)]
+pub struct StoredRecording {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+    pub protected: bool,
+}
+
+/// Следит за местом в каталоге записей: удаляет старые записи сверх квоты
+/// и говорит, можно ли начинать или продолжать запись.
+pub struct StorageManager {
+    dir: PathBuf,
+    config: StorageConfig,
+    stats: Box<dyn FsStats>,
+}
+
+impl StorageManager {
+    pub fn new(dir: &Path, config: &StorageConfig) -> Self {
+        Self::with_stats(dir, config, Box::new(GioFsStats))
+    }
+
+    pub fn with_stats(dir: &Path, config: &StorageConfig, stats: Box<dyn FsStats>) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            config: config.clone(),
+            stats,
+        }
+    }
+
+    pub fn free_space(&self) -> io::Result<u64> {
+        self.stats.free_space(&self.dir)
+    }
+
+    /// Хватает ли места, чтобы начать запись
+    pub fn check_can_start(&self) -> Result<(), String> {
+        let free = self
+            .free_space()
+            .map_err(|e| format!("Нет доступа к {}: {}", self.dir.display(), e))?;
+        if free < self.config.min_free_mb * MB {
+            return Err(format!(
+                "Мало места для записи: свободно {} МБ, нужно не меньше {} МБ",
+                free / MB,
+                self.config.min_free_mb
+            ));
+        }
+        Ok(())
+    }
+
+    /// Место заканчивается, идущую запись пора остановить
+    pub fn is_running_out(&self) -> bool {
+        self.free_space()
+            .is_ok_and(|free| free < self.config.stop_free_mb * MB)
+    }
+
+    /// Записи в каталоге и его подкаталогах (например, по дням),
+    /// от старых к новым
+    pub fn recordings(&self) -> Vec<StoredRecording> {
+        let mut recordings: Vec<StoredRecording> = recording_paths(&self.dir)
+            .into_iter()
+            .filter_map(|path| {
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some(StoredRecording {
+                    size: recording_size(&path),
+                    protected: is_protected(&path),
+                    path,
+                    modified,
+                })
+            })
+            .collect();
+
+        recordings.sort_by_key(|r| r.modified);
+        recordings
+    }
+
+    /// Удаляет самые старые незащищённые записи, пока записи занимают
+    /// больше квоты или (с `delete_oldest`) свободного места меньше
+    /// `min_free_mb`. Записи из `in_use` (текущая и ещё дописываемая)
+    /// не трогаются. Возвращает удалённые пути.
+    pub fn enforce(&self, in_use: &[PathBuf]) -> Vec<PathBuf> {
+        let recordings = self.recordings();
+        let mut used: u64 = recordings.iter().map(|r| r.size).sum();
+        let mut free = self.free_space().unwrap_or(u64::MAX);
+        let quota = self.config.quota_mb * MB;
+        let min_free = self.config.min_free_mb * MB;
+
+        let mut deleted = Vec::new();
+        for recording in recordings {
+            let over_quota = quota > 0 && used > quota;
+            let low_space = self.config.delete_oldest && free < min_free;
+            if !over_quota && !low_space {
+                break;
+            }
+            if recording.protected || in_use.contains(&recording.path) {
+                continue;
+            }
+
+            println!(
+                "Удаляем старую запись {} ({} МБ)",
+                recording.path.display(),
+                recording.size / MB
+            );
+            if let Err(e) = remove_recording(&recording.path) {
+                println!("Не удалось удалить {}: {}", recording.path.display(), e);
+                continue;
+            }
+            // Опустевший каталог дня тоже убираем
+            if let Some(parent) = recording.path.parent().filter(|p| *p != self.dir) {
+                let _ = fs::remove_dir(parent);
+            }
+            used = used.saturating_sub(recording.size);
+            free = free.saturating_add(recording.size);
+            deleted.push(recording.path);
+        }
+        deleted
+    }
+}
+
+/// Путь к файлу-метке защиты записи
+pub fn protect_marker(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(PROTECT_SUFFIX);
+    path.with_file_name(name)
+}
+
+pub fn is_protected(path: &Path) -> bool {
+    protect_marker(path).exists()
+}
+
+/// Пути записей в `dir`. Каталоги, которые не являются сессиями,
+/// просматриваются рекурсивно.
+fn recording_paths(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut paths = Vec::new();
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        if is_recording(&path) {
+            paths.push(path);
+        } else if path.is_dir() {
+            paths.extend(recording_paths(&path));
+        }
+    }
+    paths
+}
+
+fn is_recording(path: &Path) -> bool {
+    if path.is_dir() {
+        return path.join(MANIFEST_NAME).is_file();
+    }
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| RECORDING_EXTENSIONS.contains(&e))
+}
+
+fn recording_size(path: &Path) -> u64 {
+    if !path.is_dir() {
+        return fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter_map(|e| e.metadata().ok())
+                .map(|m| m.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Ставит или снимает защиту записи от удаления
+pub fn set_protected(path: &Path, protected: bool) -> io::Result<()> {
+    let marker = protect_marker(path);
+    if protected {
+        fs::write(marker, b"")
+    } else if marker.exists() {
+        fs::remove_file(marker)
+    } else {
+        Ok(())
+    }
+}
+
+/// Переименовывает запись вместе с метаданными, меткой защиты и меткой
+/// незавершённой записи.
+/// `name` - новое имя без расширения, запись остаётся в своём каталоге.
+pub fn rename_recording(path: &Path, name: &str) -> io::Result<PathBuf> {
+    let mut file_name = name.to_string();
+    if let Some(extension) = path.extension().filter(|_| !path.is_dir()) {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    let new_path = path.with_file_name(file_name);
+    if new_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} уже существует", new_path.display()),
+        ));
+    }
+
+    fs::rename(path, &new_path)?;
+    let sidecar = sidecar_path(path);
+    if sidecar.exists() {
+        fs::rename(sidecar, sidecar_path(&new_path))?;
+    }
+    if is_protected(path) {
+        fs::rename(protect_marker(path), protect_marker(&new_path))?;
+    }
+    let unfinished = unfinished_marker(path);
+    if unfinished.exists() {
+        fs::rename(unfinished, unfinished_marker(&new_path))?;
+    }
+    Ok(new_path)
+}
+
+/// Удаляет запись вместе с файлами рядом с ней
+pub fn remove_recording(path: &Path) -> io::Result<()> {
+    let _ = fs::remove_file(sidecar_path(path));
+    let _ = fs::remove_file(protect_marker(path));
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        let _ = fs::remove_file(unfinished_marker(path));
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+
+    /// Каталог записей во временном каталоге, удаляется после теста
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("ncy_storage_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Запись размером `mb` МБ, `age` секунд назад
+        fn recording(&self, name: &str, mb: u64, age: u64) -> PathBuf {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let file = File::create(&path).unwrap();
+            file.set_len(mb * MB).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Файловая система с заданным свободным местом
+    struct FakeFs(io::Result<u64>);
+
+    impl FsStats for FakeFs {
+        fn free_space(&self, _path: &Path) -> io::Result<u64> {
+            match &self.0 {
+                Ok(free) => Ok(*free),
+                Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+            }
+        }
+    }
+
+    fn manager(dir: &TestDir, free_mb: u64, config: StorageConfig) -> StorageManager {
+        StorageManager::with_stats(&dir.0, &config, Box::new(FakeFs(Ok(free_mb * MB))))
+    }
+
+    #[test]
+    fn quota_removes_oldest_first() {
+        let dir = TestDir::new("quota");
+        let oldest = dir.recording("a.mp4", 1, 300);
+        let middle = dir.recording("b.mp4", 1, 200);
+        let newest = dir.recording("c.mp4", 1, 100);
+        let storage = manager(
+            &dir,
+            1000,
+            StorageConfig {
+                quota_mb: 2,
+                ..StorageConfig::default()
+            },
+        );
+
+        assert_eq!(storage.enforce(&[]), vec![oldest.clone()]);
+        assert!(!oldest.exists());
+        assert!(middle.exists() && newest.exists());
+    }
+
+    #[test]
+    fn quota_skips_protected_and_in_use() {
+        let dir = TestDir::new("skip");
+        let protected = dir.recording("a.mp4", 1, 300);
+        let finalizing = dir.recording("b.mp4", 1, 200);
+        let newest = dir.recording("c.mkv", 1, 100);
+        set_protected(&protected, true).unwrap();
+        let storage = manager(
+            &dir,
+            1000,
+            StorageConfig {
+                quota_mb: 1,
+                ..StorageConfig::default()
+            },
+        );
+
+        assert_eq!(
+            storage.enforce(std::slice::from_ref(&finalizing)),
+            vec![newest]
+        );
+        assert!(protected.exists() && finalizing.exists());
+    }
+
+    #[test]
+    fn low_space_removes_until_min_free() {
+        let dir = TestDir::new("low_space");
+        let a = dir.recording("a.mp4", 1, 300);
+        let b = dir.recording("b.mp4", 1, 200);
+        let c = dir.recording("c.mp4", 1, 100);
+        let config = StorageConfig {
+            min_free_mb: 3,
+            delete_oldest: true,
+            ..StorageConfig::default()
+        };
+
+        assert_eq!(manager(&dir, 1, config).enforce(&[]), vec![a, b]);
+        assert!(c.exists());
+    }
+
+    #[test]
+    fn low_space_keeps_recordings_without_delete_oldest() {
+        let dir = TestDir::new("keep");
+        let a = dir.recording("a.mp4", 1, 100);
+        let storage = manager(
+            &dir,
+            1,
+            StorageConfig {
+                min_free_mb: 3,
+                delete_oldest: false,
+                ..StorageConfig::default()
+            },
+        );
+
+        assert!(storage.enforce(&[]).is_empty());
+        assert!(a.exists());
+        assert!(storage.check_can_start().is_err());
+    }
+
+    #[test]
+    fn free_space_limits_start_and_stop() {
+        let dir = TestDir::new("limits");
+        let config = StorageConfig {
+            min_free_mb: 100,
+            stop_free_mb: 20,
+            ..StorageConfig::default()
+        };
+
+        let plenty = manager(&dir, 500, config.clone());
+        assert!(plenty.check_can_start().is_ok());
+        assert!(!plenty.is_running_out());
+
+        let low = manager(&dir, 50, config.clone());
+        assert!(low.check_can_start().is_err());
+        assert!(!low.is_running_out());
+
+        let full = manager(&dir, 10, config.clone());
+        assert!(full.is_running_out());
+
+        let broken = StorageManager::with_stats(
+            &dir.0,
+            &config,
+            Box::new(FakeFs(Err(io::Error::other("нет диска")))),
+        );
+        assert!(broken.check_can_start().is_err());
+        assert!(!broken.is_running_out());
+    }
+
+    #[test]
+    fn emptied_day_dir_is_removed() {
+        let dir = TestDir::new("days");
+        let old = dir.recording("2026-01-01/a.mp4", 1, 300);
+        let new = dir.recording("2026-01-02/b.mp4", 1, 100);
+        let storage = manager(
+            &dir,
+            1000,
+            StorageConfig {
+                quota_mb: 1,
+                ..StorageConfig::default()
+            },
+        );
+
+        assert_eq!(storage.enforce(&[]), vec![old.clone()]);
+        assert!(!old.parent().unwrap().exists());
+        assert!(new.exists());
+    }
+
+    #[test]
+    fn rename_moves_markers() {
+        let dir = TestDir::new("rename");
+        let path = dir.recording("a.mkv", 0, 0);
+        File::create(sidecar_path(&path)).unwrap();
+        File::create(unfinished_marker(&path)).unwrap();
+        set_protected(&path, true).unwrap();
+
+        let renamed = rename_recording(&path, "b").unwrap();
+        assert_eq!(renamed, dir.0.join("b.mkv"));
+        assert!(renamed.exists());
+        assert!(sidecar_path(&renamed).exists());
+        assert!(unfinished_marker(&renamed).exists());
+        assert!(is_protected(&renamed));
+        assert!(!sidecar_path(&path).exists());
+        assert!(!unfinished_marker(&path).exists());
+        assert!(!protect_marker(&path).exists());
+    }
+}
@@ -0,0 +1,391 @@
+use gstreamer::prelude::*;
+use gstreamer::{Bin, Fraction};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Формат, в котором V4L2 устройство отдаёт кадры
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFormat {
+    /// Сжатый MJPEG (image/jpeg)
+    Mjpeg,
+    /// YUYV 4:2:2, обычный формат аналоговых карт захвата
+    #[serde(alias = "yuyv")]
+    Yuy2,
+    /// NV12 4:2:0
+    Nv12,
+    /// H.264 от UVC камер с аппаратным кодером
+    H264,
+    /// Любое несжатое видео (video/x-raw), формат выбирает камера
+    Raw,
+}
+
+impl PixelFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "mjpeg" => Some(PixelFormat::Mjpeg),
+            "yuy2" | "yuyv" => Some(PixelFormat::Yuy2),
+            "nv12" => Some(PixelFormat::Nv12),
+            "h264" => Some(PixelFormat::H264),
+            "raw" => Some(PixelFormat::Raw),
+            _ => None,
+        }
+    }
+
+    /// Формат по имени из caps: "MJPEG", "H264" или формат video/x-raw
+    pub fn from_caps_format(name: &str) -> Self {
+        match name {
+            "MJPEG" => PixelFormat::Mjpeg,
+            "H264" => PixelFormat::H264,
+            "YUY2" => PixelFormat::Yuy2,
+            "NV12" => PixelFormat::Nv12,
+            _ => PixelFormat::Raw,
+        }
+    }
+
+    /// Несжатый ли формат
+    pub fn is_raw(&self) -> bool {
+        matches!(
+            self,
+            PixelFormat::Yuy2 | PixelFormat::Nv12 | PixelFormat::Raw
+        )
+    }
+
+    /// Caps, которые запрашиваются у камеры, и цепочка декодирования после них.
+    /// MJPEG до декодера отводится в tee `JPEG_TAP_NAME` для записи без
+    /// перекодирования.
+    pub fn capture_chain(&self, width: i32, height: i32, framerate: Fraction) -> String {
+        let size = format!(
+            "width={},height={},framerate={}/{}",
+            width,
+            height,
+            framerate.numer(),
+            framerate.denom()
+        );
+        match self {
+            PixelFormat::Mjpeg => format!(
+                "image/jpeg,{} ! tee name={} ! queue ! jpegdec",
+                size, JPEG_TAP_NAME
+            ),
+            PixelFormat::Yuy2 => format!("video/x-raw,format=YUY2,{}", size),
+            PixelFormat::Nv12 => format!("video/x-raw,format=NV12,{}", size),
+            PixelFormat::H264 => format!(
+                "video/x-h264,stream-format=byte-stream,{} ! h264parse ! avdec_h264",
+                size
+            ),
+            PixelFormat::Raw => format!("video/x-raw,{}", size),
+        }
+    }
+}
+
+/// Кодек RTP потока
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RtpCodec {
+    H264,
+    H265,
+}
+
+impl RtpCodec {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "h264" => Some(RtpCodec::H264),
+            "h265" => Some(RtpCodec::H265),
+            _ => None,
+        }
+    }
+
+    /// Значение encoding-name в RTP caps
+    fn encoding_name(&self) -> &'static str {
+        match self {
+            RtpCodec::H264 => "H264",
+            RtpCodec::H265 => "H265",
+        }
+    }
+
+    /// Цепочка от RTP пакетов до несжатого видео
+    fn depay_chain(&self) -> &'static str {
+        match self {
+            RtpCodec::H264 => "rtph264depay ! h264parse ! avdec_h264",
+            RtpCodec::H265 => "rtph265depay ! h265parse ! avdec_h265",
+        }
+    }
+}
+
+/// Транспорт RTSP сессии
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RtspTransport {
+    /// rtspsrc сам выбирает UDP или TCP
+    Auto,
+    Tcp,
+    Udp,
+}
+
+impl RtspTransport {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(RtspTransport::Auto),
+            "tcp" => Some(RtspTransport::Tcp),
+            "udp" => Some(RtspTransport::Udp),
+            _ => None,
+        }
+    }
+
+    /// Значение свойства `protocols` у rtspsrc
+    fn protocols(&self) -> &'static str {
+        match self {
+            RtspTransport::Auto => "udp+udp-mcast+tcp",
+            RtspTransport::Tcp => "tcp",
+            RtspTransport::Udp => "udp",
+        }
+    }
+}
+
+/// Имя tee со сжатыми JPEG кадрами внутри bin источника
+pub const JPEG_TAP_NAME: &str = "jpeg_tap";
+
+/// Имя rtpjitterbuffer внутри bin источника, по нему читается статистика
+pub const JITTERBUFFER_NAME: &str = "jitter";
+
+/// Имя rtspsrc внутри bin источника
+const RTSPSRC_NAME: &str = "rtsp";
+
+/// Источник видео, который подаётся на вход `tee name=t`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum VideoSource {
+    /// Камера или карта захвата V4L2
+    V4l2 {
+        device: String,
+        #[serde(default = "default_format")]
+        format: PixelFormat,
+    },
+    /// Тестовая картинка videotestsrc, для работы без камеры
+    #[serde(rename = "test")]
+    TestSrc {
+        #[serde(default = "default_pattern")]
+        pattern: String,
+    },
+    /// Воспроизведение локального файла
+    File { path: String },
+    /// RTP поток H.264/H.265 по UDP, например от цифровой FPV системы.
+    /// `rtp-h264` - прежнее имя из старых файлов конфигурации.
+    #[serde(alias = "rtp-h264")]
+    Rtp {
+        port: u16,
+        #[serde(default = "default_codec")]
+        codec: RtpCodec,
+        #[serde(default = "default_payload")]
+        payload: u8,
+        #[serde(default = "default_latency")]
+        latency: u32,
+    },
+    /// RTSP поток с IP камеры
+    Rtsp {
+        location: String,
+        #[serde(default = "default_latency")]
+        latency: u32,
+        #[serde(default = "default_transport")]
+        transport: RtspTransport,
+        #[serde(default)]
+        user: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        /// Через сколько секунд без кадров переподключаться
+        #[serde(default = "default_rtsp_timeout")]
+        timeout: u64,
+    },
+}
+
+fn default_format() -> PixelFormat {
+    PixelFormat::Mjpeg
+}
+
+fn default_pattern() -> String {
+    String::from("smpte")
+}
+
+fn default_codec() -> RtpCodec {
+    RtpCodec::H264
+}
+
+fn default_transport() -> RtspTransport {
+    RtspTransport::Auto
+}
+
+fn default_rtsp_timeout() -> u64 {
+    5
+}
+
+fn default_payload() -> u8 {
+    96
+}
+
+fn default_latency() -> u32 {
+    200
+}
+
+impl VideoSource {
+    /// Источник заданного типа с настройками по умолчанию
+    pub fn default_for(kind: &str) -> Option<Self> {
+        match kind {
+            "v4l2" => Some(VideoSource::V4l2 {
+                device: String::from("/dev/video0"),
+                format: default_format(),
+            }),
+            "test" => Some(VideoSource::TestSrc {
+                pattern: default_pattern(),
+            }),
+            "file" => Some(VideoSource::File {
+                path: String::new(),
+            }),
+            "rtp" => Some(VideoSource::Rtp {
+                port: 5600,
+                codec: default_codec(),
+                payload: default_payload(),
+                latency: default_latency(),
+            }),
+            "rtsp" => Some(VideoSource::Rtsp {
+                location: String::new(),
+                latency: default_latency(),
+                transport: default_transport(),
+                user: None,
+                password: None,
+                timeout: default_rtsp_timeout(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Возвращает часть описания pipeline от источника до несжатого видео.
+    /// На выходе сегмента всегда стоит `videoconvert`, к нему подключается `tee`.
+    pub fn pipeline_segment(&self, width: i32, height: i32, framerate: Fraction) -> String {
+        let segment = match self {
+            VideoSource::V4l2 { device, format } => format!(
+                "v4l2src device={} ! {}",
+                device,
+                format.capture_chain(width, height, framerate)
+            ),
+            VideoSource::TestSrc { pattern } => format!(
+                "videotestsrc is-live=true pattern={} ! video/x-raw,width={},height={},framerate={}/{}",
+                pattern,
+                width,
+                height,
+                framerate.numer(),
+                framerate.denom()
+            ),
+            // identity sync=true нужен, чтобы файл проигрывался в реальном
+            // времени: gtk4paintablesink работает с sync=false
+            VideoSource::File { path } => format!(
+                "filesrc location=\"{}\" ! decodebin ! videoconvert ! videoscale ! \
+                video/x-raw,width={},height={} ! identity sync=true",
+                path, width, height
+            ),
+            VideoSource::Rtp {
+                port,
+                codec,
+                payload,
+                latency,
+            } => format!(
+                "udpsrc port={} caps=\"application/x-rtp,media=video,clock-rate=90000,\
+                encoding-name={},payload={}\" ! rtpjitterbuffer name={} latency={} ! {}",
+                port,
+                codec.encoding_name(),
+                payload,
+                JITTERBUFFER_NAME,
+                latency,
+                codec.depay_chain()
+            ),
+            VideoSource::Rtsp {
+                location,
+                latency,
+                transport,
+                timeout,
+                ..
+            } => format!(
+                "rtspsrc name={} location=\"{}\" latency={} protocols={} \
+                timeout={} tcp-timeout={} ! decodebin",
+                RTSPSRC_NAME,
+                location,
+                latency,
+                transport.protocols(),
+                timeout * 1_000_000,
+                timeout * 1_000_000
+            ),
+        };
+
+        format!("{} ! videoconvert", segment)
+    }
+
+    /// Задаёт свойства, которые нельзя безопасно подставить в строку
+    /// `pipeline_segment`: логин и пароль RTSP могут содержать кавычки
+    pub fn configure_bin(&self, bin: &Bin) {
+        let VideoSource::Rtsp { user, password, .. } = self else {
+            return;
+        };
+        let Some(rtspsrc) = bin.by_name(RTSPSRC_NAME) else {
+            return;
+        };
+        if let Some(user) = user {
+            rtspsrc.set_property("user-id", user);
+        }
+        if let Some(password) = password {
+            rtspsrc.set_property("user-pw", password);
+        }
+    }
+
+    /// Через сколько времени без кадров источник считается зависшим и
+    /// пересоздаётся. Для локальных источников не задано: их ошибки
+    /// приходят на шину.
+    pub fn stall_timeout(&self) -> Option<Duration> {
+        match self {
+            VideoSource::Rtsp { timeout, .. } => Some(Duration::from_secs(*timeout)),
+            _ => None,
+        }
+    }
+
+    /// Отдаёт ли источник MJPEG, который можно записывать без перекодирования
+    pub fn is_mjpeg(&self) -> bool {
+        matches!(
+            self,
+            VideoSource::V4l2 {
+                format: PixelFormat::Mjpeg,
+                ..
+            }
+        )
+    }
+
+    /// Устройство или адрес источника, для метаданных записи
+    pub fn device(&self) -> String {
+        match self {
+            VideoSource::V4l2 { device, .. } => device.clone(),
+            VideoSource::TestSrc { pattern } => format!("videotestsrc {}", pattern),
+            VideoSource::File { path } => path.clone(),
+            VideoSource::Rtp { port, .. } => format!("udp://0.0.0.0:{}", port),
+            VideoSource::Rtsp { location, .. } => location.clone(),
+        }
+    }
+
+    /// Копия без пароля, чтобы его не было в файлах рядом с записями
+    pub fn without_secrets(&self) -> Self {
+        let mut source = self.clone();
+        if let VideoSource::Rtsp { password, .. } = &mut source
+            && password.is_some()
+        {
+            *password = Some(String::from("***"));
+        }
+        source
+    }
+
+    /// Короткое имя источника для логов
+    pub fn name(&self) -> &'static str {
+        match self {
+            VideoSource::V4l2 { .. } => "v4l2",
+            VideoSource::TestSrc { .. } => "test",
+            VideoSource::File { .. } => "file",
+            VideoSource::Rtp { .. } => "rtp",
+            VideoSource::Rtsp { .. } => "rtsp",
+        }
+    }
+}